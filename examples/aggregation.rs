@@ -37,14 +37,14 @@ pub async fn main() {
     // Gauge
     // Explicitly creating the metric value before-hand
     let gauge_metric =
-        Metric::new_gauge(EXAMPLE_GAUGE_METRIC).with_tags(&["test_tag2:test_tag_value2"]);
+        Metric::new_gauge(EXAMPLE_GAUGE_METRIC).with_tags(["test_tag2:test_tag_value2"]);
     let my_gauge = registry
         .register_gauge(gauge_metric)
         .expect("Failed to register gauge metric!");
 
     // Timing count
     let timing_count_metric = Metric::new_timing_count(EXAMPLE_TIMING_COUNT_METRIC)
-        .with_tags(&["test_tag2:test_tag_value2"]);
+        .with_tags(["test_tag2:test_tag_value2"]);
     let timing_instrument = registry
         .register_timing_count(timing_count_metric)
         .expect("Failed to register count metric!");
@@ -1,16 +1,36 @@
-use std::{borrow::Cow, env, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    io,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
 
-use dogstatsd::*;
 use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+use crate::{
+    protocol::{Encoder, MetricKind, MetricOptions},
+    sink::{Sink, SinkResult},
+    transport::{Endpoint, Transport},
+};
 
 pub const STATSD_HOST_ENV: &str = "STATSD_HOST";
 pub const STATSD_PORT_ENV: &str = "STATSD_PORT";
-const DEFAULT_ORIGIN: &str = "0.0.0.0:0";
-// Port 8125(UDP) is for metrics,
-// port 8126(TCP) is for Datadog APM (tracing)
+/// Takes precedence over [STATSD_HOST_ENV] and [STATSD_PORT_ENV], e.g. `unix:///var/run/datadog/dsd.socket`
+/// or `udp://localhost:8125`, see [Endpoint].
+pub const DOGSTATSD_URL_ENV: &str = "DD_DOGSTATSD_URL";
+// Upper bound on values packed into a single distribution datagram
+const DISTRIBUTION_VALUES_PER_PACKET: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum DogstatsdError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
 
-const DEFAULT_HOST: &str = "0.0.0.0";
-const DEFAULT_PORT: &str = "8125";
+pub type DogstatsdResult = Result<(), DogstatsdError>;
 
 static SYNC_INSTANCE: OnceCell<GnortClient> = OnceCell::new();
 
@@ -24,8 +44,12 @@ pub(crate) fn sync_client() -> &'static GnortClient {
 /// using [crate::registry::MetricsRegistry].
 #[derive(Clone)]
 pub struct GnortClient {
-    /// The Arc around the dogstatsd client is a hack to work around the lack of a native Clone implementation.
-    client: Arc<Client>,
+    /// Shared so clones write to the same socket.
+    transport: Arc<Transport>,
+    encoder: Encoder,
+    /// Newline separated lines written through [Sink] that haven't filled a packet yet.
+    batch: Arc<Mutex<Vec<u8>>>,
+    max_packet_size: usize,
 }
 
 pub(crate) fn get_default_tags() -> Vec<String> {
@@ -33,20 +57,51 @@ pub(crate) fn get_default_tags() -> Vec<String> {
     let version = env::var("DD_VERSION").map(|t| format!("version:{}", t));
     let service = env::var("DD_SERVICE").map(|t| format!("service:{}", t));
     let optional_tags = vec![env, version, service];
-    let only_existing_tags = optional_tags.into_iter().filter_map(|t| t.ok()).collect();
-    only_existing_tags
+    optional_tags.into_iter().filter_map(|t| t.ok()).collect()
 }
 
 impl GnortClient {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, DogstatsdError> {
         let no_tags = &[] as &[&str];
         Self::new(None, no_tags)
     }
 
+    /// Connects to the [Endpoint] configured by `DD_DOGSTATSD_URL`, or `STATSD_HOST` and `STATSD_PORT`.
     pub fn new<I, T>(
         namespace: Option<&str>,
         extra_default_tags: I,
-    ) -> Result<GnortClient, dogstatsd::DogstatsdError>
+    ) -> Result<GnortClient, DogstatsdError>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        Self::with_endpoint(&Endpoint::from_env()?, namespace, extra_default_tags)
+    }
+
+    pub fn with_endpoint<I, T>(
+        endpoint: &Endpoint,
+        namespace: Option<&str>,
+        extra_default_tags: I,
+    ) -> Result<GnortClient, DogstatsdError>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        let transport = Transport::connect(endpoint)?;
+        Ok(Self::with_transport(
+            transport,
+            namespace,
+            extra_default_tags,
+        ))
+    }
+
+    /// Sends over an already connected [Transport], such as one half of a `UnixDatagram::pair()`.
+    pub fn with_transport<I, T>(
+        transport: Transport,
+        namespace: Option<&str>,
+        extra_default_tags: I,
+    ) -> GnortClient
     where
         T: AsRef<str>,
         I: IntoIterator<Item = T>,
@@ -57,27 +112,112 @@ impl GnortClient {
             .collect::<Vec<_>>();
         extra_default_tags.sort();
 
-        let statsd_host = env::var(STATSD_HOST_ENV).unwrap_or(DEFAULT_HOST.to_string());
-        let statsd_port = env::var(STATSD_PORT_ENV).unwrap_or(DEFAULT_PORT.to_string());
-        let udp_origin = DEFAULT_ORIGIN.to_string();
-        let udp_target = format!("{}:{}", statsd_host, statsd_port);
-        let actual_namespace = namespace.unwrap_or("");
         let mut default_tags = get_default_tags();
         default_tags.extend(extra_default_tags);
-        let options = Options {
-            socket_path: None,
-            batching_options: None,
-            default_tags,
-            from_addr: udp_origin,
-            to_addr: udp_target,
-            namespace: actual_namespace.to_string(),
-        };
-        let client = Client::new(options)?;
-
-        let gnort_client = GnortClient {
-            client: Arc::new(client),
-        };
-        Ok(gnort_client)
+        GnortClient {
+            max_packet_size: transport.default_max_packet_size(),
+            transport: Arc::new(transport),
+            encoder: Encoder::new(namespace.unwrap_or(""), default_tags),
+            batch: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Sends `container_id` with every metric and event so the agent can tag them with the
+    /// container they came from, see [Encoder::with_container_id].
+    pub fn with_container_id(mut self, container_id: impl Into<String>) -> Self {
+        self.encoder = self.encoder.with_container_id(container_id);
+        self
+    }
+
+    /// Upper bound on the size of the packets the [Sink] implementation batches metrics into,
+    /// defaults to 1432 bytes for UDP and 8192 bytes for Unix domain sockets.
+    /// A single metric larger than this is still sent on its own.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    fn lock_batch(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.batch.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Appends `line` to the batch, sending the batch first if `line` wouldn't fit.
+    fn buffer_line(&self, line: String) -> DogstatsdResult {
+        let mut batch = self.lock_batch();
+        let mut result = Ok(());
+        if !batch.is_empty() && batch.len() + 1 + line.len() > self.max_packet_size {
+            // Taken rather than cleared after sending so a failed send doesn't grow the batch forever
+            let packet = std::mem::take(&mut *batch);
+            result = self.transport.send(&packet);
+        }
+        if !batch.is_empty() {
+            batch.push(b'\n');
+        }
+        batch.extend_from_slice(line.as_bytes());
+        Ok(result?)
+    }
+
+    /// Sends whatever metrics written through [Sink] are still waiting to fill a packet.
+    /// [MetricsRegistry](crate::MetricsRegistry) calls this at the end of every emission cycle.
+    pub fn flush(&self) -> DogstatsdResult {
+        let packet = std::mem::take(&mut *self.lock_batch());
+        if packet.is_empty() {
+            return Ok(());
+        }
+        Ok(self.transport.send(&packet)?)
+    }
+
+    fn buffer_metric<V: Display>(
+        &self,
+        name: &str,
+        values: &[V],
+        kind: MetricKind,
+        tags: &[&str],
+        options: MetricOptions,
+    ) -> SinkResult {
+        let line = self.format_metric(name, values, kind, tags, options);
+        Ok(self.buffer_line(line)?)
+    }
+
+    fn send_line(&self, line: String) -> DogstatsdResult {
+        Ok(self.transport.send(line.as_bytes())?)
+    }
+
+    fn format_metric<V, I, T>(
+        &self,
+        stat: &str,
+        values: &[V],
+        kind: MetricKind,
+        tags: I,
+        options: MetricOptions,
+    ) -> String
+    where
+        V: Display,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut line = String::new();
+        self.encoder
+            .encode_metric(&mut line, stat, values, kind, tags, options);
+        line
+    }
+
+    /// Sends a metric of any [MetricKind] right away, with a sample rate or timestamp from `options`.
+    /// Several `values` are packed into one line.
+    pub fn send_metric<V, I, T>(
+        &self,
+        stat: &str,
+        values: &[V],
+        kind: MetricKind,
+        tags: I,
+        options: MetricOptions,
+    ) -> DogstatsdResult
+    where
+        V: Display,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.send_line(self.format_metric(stat, values, kind, tags, options))
     }
 
     pub fn count<'a, I, S, T>(&self, stat: S, count: i64, tags: I) -> DogstatsdResult
@@ -86,7 +226,13 @@ impl GnortClient {
         S: Into<Cow<'a, str>>,
         T: AsRef<str>,
     {
-        self.client.count(stat, count, tags)
+        self.send_metric(
+            &stat.into(),
+            &[count],
+            MetricKind::Count,
+            tags,
+            MetricOptions::default(),
+        )
     }
 
     pub fn event<'a, I, S, SS, T>(&self, title: S, text: SS, tags: I) -> DogstatsdResult
@@ -96,7 +242,10 @@ impl GnortClient {
         SS: Into<Cow<'a, str>>,
         T: AsRef<str>,
     {
-        self.client.event(title, text, tags)
+        let mut line = String::new();
+        self.encoder
+            .encode_event(&mut line, &title.into(), &text.into(), tags);
+        self.send_line(line)
     }
 
     pub fn gauge<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
@@ -106,7 +255,61 @@ impl GnortClient {
         SS: Into<Cow<'a, str>>,
         T: AsRef<str>,
     {
-        self.client.gauge(stat, val, tags)
+        self.send_metric(
+            &stat.into(),
+            &[val.into()],
+            MetricKind::Gauge,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+
+    pub fn distribution<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_metric(
+            &stat.into(),
+            &[val.into()],
+            MetricKind::Distribution,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+
+    pub fn histogram<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_metric(
+            &stat.into(),
+            &[val.into()],
+            MetricKind::Histogram,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+
+    pub fn set<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_metric(
+            &stat.into(),
+            &[val.into()],
+            MetricKind::Set,
+            tags,
+            MetricOptions::default(),
+        )
     }
 
     pub fn timing<'a, I, S, T>(&self, stat: S, milliseconds: i64, tags: I) -> DogstatsdResult
@@ -115,6 +318,199 @@ impl GnortClient {
         S: Into<Cow<'a, str>>,
         T: AsRef<str>,
     {
-        self.client.timing(stat, milliseconds, tags)
+        self.send_metric(
+            &stat.into(),
+            &[milliseconds],
+            MetricKind::Timing,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+}
+
+/// Metrics are batched into packets of up to [GnortClient::with_max_packet_size] bytes,
+/// the last partial packet is sent by [Sink::flush].
+impl Sink for GnortClient {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult {
+        self.buffer_metric(
+            name,
+            &[value],
+            MetricKind::Count,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult {
+        self.buffer_metric(
+            name,
+            &[value],
+            MetricKind::Gauge,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult {
+        self.buffer_metric(
+            name,
+            &[milliseconds],
+            MetricKind::Timing,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+    fn set(&self, name: &str, value: &str, tags: &[&str]) -> SinkResult {
+        self.buffer_metric(
+            name,
+            &[value],
+            MetricKind::Set,
+            tags,
+            MetricOptions::default(),
+        )
+    }
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult {
+        // Multiple values per line (DogStatsD protocol v1.1)
+        for chunk in values.chunks(DISTRIBUTION_VALUES_PER_PACKET) {
+            self.buffer_metric(
+                name,
+                chunk,
+                MetricKind::Distribution,
+                tags,
+                MetricOptions::default(),
+            )?;
+        }
+        Ok(())
+    }
+    /// Bins with the same count share a line sampled at `1/count`, so the agent weighs every
+    /// value as `count` samples without it being repeated on the wire.
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        let mut by_count: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+        for &(value, count) in bins.iter().filter(|(_, count)| *count > 0) {
+            by_count.entry(count).or_default().push(value);
+        }
+        for (count, values) in by_count {
+            let options = MetricOptions::default().with_sample_rate(1.0 / count as f64);
+            for chunk in values.chunks(DISTRIBUTION_VALUES_PER_PACKET) {
+                self.buffer_metric(name, chunk, MetricKind::Distribution, tags, options)?;
+            }
+        }
+        Ok(())
+    }
+    fn flush(&self) -> SinkResult {
+        Ok(GnortClient::flush(self)?)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{
+        io::Read,
+        os::unix::net::{UnixDatagram, UnixStream},
+    };
+
+    use super::*;
+
+    fn client(transport: Transport) -> GnortClient {
+        let mut client = GnortClient::with_transport(transport, Some("gnort"), ["team:metrics"]);
+        // Don't depend on DD_ENV and friends being unset
+        client.encoder = Encoder::new("gnort", vec!["team:metrics".to_string()]);
+        client
+    }
+
+    #[test]
+    fn test_unix_datagram_transport() {
+        let (local, agent) = UnixDatagram::pair().unwrap();
+        let client = client(local.into());
+        client.count("test.count", 3, ["tag:x"]).unwrap();
+        client.event("deployed", "v2", [] as [&str; 0]).unwrap();
+        let mut buf = [0; 512];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"gnort.test.count:3|c|#tag:x,team:metrics".as_slice()
+        );
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"_e{8,2}:deployed|v2|#team:metrics".as_slice());
+    }
+
+    #[test]
+    fn test_unix_stream_transport() {
+        let (local, mut agent) = UnixStream::pair().unwrap();
+        let client = client(local.into());
+        Sink::gauge(&client, "test.gauge", 1.5, &[]).unwrap();
+        Sink::flush(&client).unwrap();
+        let mut length = [0; 4];
+        agent.read_exact(&mut length).unwrap();
+        let mut payload = vec![0; u32::from_le_bytes(length) as usize];
+        agent.read_exact(&mut payload).unwrap();
+        assert_eq!(payload, b"gnort.test.gauge:1.5|g|#team:metrics");
+    }
+
+    #[test]
+    fn test_batching() {
+        let (local, agent) = UnixDatagram::pair().unwrap();
+        agent.set_nonblocking(true).unwrap();
+        // Fits two of the lines below but not three
+        let client = client(local.into()).with_max_packet_size(100);
+        for value in 1..=3 {
+            Sink::count(&client, "test.count", value, &["tag:x"]).unwrap();
+        }
+        let mut buf = [0; 512];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"gnort.test.count:1|c|#tag:x,team:metrics\ngnort.test.count:2|c|#tag:x,team:metrics"
+                .as_slice()
+        );
+        assert!(agent.recv(&mut buf).is_err());
+        Sink::flush(&client).unwrap();
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"gnort.test.count:3|c|#tag:x,team:metrics".as_slice()
+        );
+        // Nothing left to send
+        Sink::flush(&client).unwrap();
+        assert!(agent.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_weighted_distribution() {
+        let (local, agent) = UnixDatagram::pair().unwrap();
+        let client = client(local.into());
+        let bins = [(1.5, 1), (2.5, 4), (3.5, 1), (4.5, 4)];
+        Sink::weighted_distribution(&client, "test.dist", &bins, &[]).unwrap();
+        Sink::flush(&client).unwrap();
+        let mut buf = [0; 512];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"gnort.test.dist:1.5:3.5|d|#team:metrics\ngnort.test.dist:2.5:4.5|d|@0.25|#team:metrics"
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_registry_emission_is_batched() {
+        let (local, agent) = UnixDatagram::pair().unwrap();
+        agent.set_nonblocking(true).unwrap();
+        let registry = crate::MetricsRegistry::new(
+            crate::RegistryConfig::default()
+                .with_client(client(local.into()))
+                .with_emission(crate::EmissionMode::Manual),
+        );
+        for tag in ["a", "b", "c"] {
+            registry
+                .register_count(crate::Metric::from("test.batch.count").with_tags([tag]))
+                .unwrap()
+                .increment();
+        }
+        registry.flush();
+        let mut buf = [0; 8192];
+        let len = agent.recv(&mut buf).unwrap();
+        let packet = std::str::from_utf8(&buf[..len]).unwrap();
+        // Three counts and the registry's own timing gauge
+        assert_eq!(packet.lines().count(), 4);
+        assert!(packet.contains("gnort.test.batch.count:1|c|#b,team:metrics"));
+        assert!(agent.recv(&mut buf).is_err());
     }
 }
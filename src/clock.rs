@@ -0,0 +1,100 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Time source for a [MetricsRegistry](crate::MetricsRegistry)'s emission schedule and rate limiting.
+/// Swap in a [FakeClock] together with [EmissionMode::Manual](crate::EmissionMode::Manual) to test
+/// time-dependent behavior without sleeping.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since some fixed reference point, e.g. when the clock was created.
+    fn now(&self) -> Duration;
+    /// Wall-clock time since the UNIX epoch, used to align observation windows.
+    fn since_epoch(&self) -> Duration;
+    /// Blocks for `duration`, used when emission is rate limited.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Monotonic wall time, the default.
+#[derive(Clone, Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+    fn since_epoch(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// A clock that only moves when told to, like governor's `FakeRelativeClock`. It starts at
+/// the UNIX epoch and clones share the same time. Sleeping advances the clock instead of blocking.
+#[derive(Clone, Debug, Default)]
+pub struct FakeClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl FakeClock {
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+    fn since_epoch(&self) -> Duration {
+        self.now()
+    }
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+/// Lets governor's rate limiter run on a registry's [Clock].
+#[derive(Clone)]
+pub(crate) struct GovernorClock(pub(crate) Arc<dyn Clock>);
+
+impl governor::clock::Clock for GovernorClock {
+    type Instant = Duration;
+
+    fn now(&self) -> Duration {
+        self.0.now()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock() {
+        let clock = FakeClock::default();
+        let shared = clock.clone();
+        assert_eq!(clock.now(), Duration::ZERO);
+        clock.advance(Duration::from_secs(2));
+        shared.sleep(Duration::from_millis(500));
+        assert_eq!(clock.now(), Duration::from_millis(2_500));
+        assert_eq!(shared.now(), clock.now());
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{clock::Clock, MetricsRegistry, ShutdownError, WindowAlignment};
+
+/// When emission cycles are due. Deadlines are computed from the first one rather than from
+/// when the previous cycle finished, so windows don't slide over long runs.
+pub(crate) struct Schedule {
+    clock: Arc<dyn Clock>,
+    alignment: WindowAlignment,
+    delay: Duration,
+    period: Duration,
+}
+
+impl Schedule {
+    pub(crate) fn new(
+        clock: Arc<dyn Clock>,
+        alignment: WindowAlignment,
+        delay: Duration,
+        period: Duration,
+    ) -> Self {
+        Self {
+            clock,
+            alignment,
+            delay,
+            // A zero period would emit in a hot loop and can't be aligned to
+            period: period.max(Duration::from_millis(1)),
+        }
+    }
+    /// Relative alignment runs on the clock's monotonic time, epoch alignment on wall time.
+    fn now(&self) -> Duration {
+        match self.alignment {
+            WindowAlignment::Relative => self.clock.now(),
+            WindowAlignment::Epoch { .. } => self.clock.since_epoch(),
+        }
+    }
+    /// Windows missed entirely are skipped rather than emitted back to back.
+    fn skip_to_future(&self, deadline: Duration, now: Duration) -> Duration {
+        if deadline > now {
+            return deadline;
+        }
+        let period = self.period.as_nanos();
+        let missed = (now - deadline).as_nanos() / period;
+        Duration::from_nanos((deadline.as_nanos() + (missed + 1) * period) as u64)
+    }
+    pub(crate) fn first(&self) -> Duration {
+        let now = self.now();
+        match self.alignment {
+            WindowAlignment::Relative => now + self.delay,
+            // The first, partial window ends on the next boundary
+            WindowAlignment::Epoch { offset } => {
+                let offset =
+                    Duration::from_nanos((offset.as_nanos() % self.period.as_nanos()) as u64);
+                self.skip_to_future(offset, now)
+            }
+        }
+    }
+    pub(crate) fn next(&self, deadline: Duration) -> Duration {
+        self.skip_to_future(deadline + self.period, self.now())
+    }
+    pub(crate) fn is_due(&self, deadline: Duration) -> bool {
+        self.now() >= deadline
+    }
+    pub(crate) fn until(&self, deadline: Duration) -> Duration {
+        deadline.saturating_sub(self.now())
+    }
+}
+
+/// Whatever is driving a registry's emission cycles.
+pub(crate) enum EmitterHandle {
+    Thread(JoinHandle<()>),
+    #[cfg(feature = "tokio")]
+    Task(tokio::task::JoinHandle<()>),
+}
+
+impl EmitterHandle {
+    /// Only called once the emitter has marked itself stopped, so this doesn't block for long.
+    pub(crate) fn join(self) -> Result<(), ShutdownError> {
+        match self {
+            EmitterHandle::Thread(handle) => handle.join().map_err(|_| ShutdownError::Panicked),
+            // The task already flushed and marked itself stopped, this only cuts short its return
+            #[cfg(feature = "tokio")]
+            EmitterHandle::Task(handle) => {
+                handle.abort();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct EmitterState {
+    pub(crate) stopping: bool,
+    pub(crate) stopped: bool,
+    pub(crate) handle: Option<EmitterHandle>,
+}
+
+/// Shared between the registry and its emitter. The thread sleeps on `wakeup` and the tokio task
+/// waits on `notify` so a shutdown can interrupt the wait between observation windows.
+#[derive(Default)]
+pub(crate) struct Emitter {
+    state: Mutex<EmitterState>,
+    pub(crate) wakeup: Condvar,
+    #[cfg(feature = "tokio")]
+    notify: tokio::sync::Notify,
+}
+
+impl Emitter {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, EmitterState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Spawns and installs a new emitter, any previous one must have been shut down. The state
+    /// stays locked until the handle is stored so the emitter can't see a stale `stopping` and
+    /// a concurrent shutdown can't miss it.
+    pub(crate) fn install(&self, spawn: impl FnOnce() -> EmitterHandle) {
+        let mut state = self.lock();
+        state.stopping = false;
+        state.stopped = false;
+        state.handle = Some(spawn());
+    }
+    /// Wakes the emitter up after `stopping` was set.
+    pub(crate) fn notify_stop(&self) {
+        self.wakeup.notify_all();
+        #[cfg(feature = "tokio")]
+        self.notify.notify_one();
+    }
+    /// Sleeps for `duration` unless a shutdown is signalled, returns whether we're stopping.
+    fn sleep(&self, duration: Duration) -> bool {
+        let state = self.lock();
+        let (state, _) = self
+            .wakeup
+            .wait_timeout_while(state, duration, |state| !state.stopping)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.stopping
+    }
+    fn mark_stopped(&self) {
+        self.lock().stopped = true;
+        self.wakeup.notify_all();
+    }
+}
+
+/// We have to clone the registry and let the user keep theirs so
+/// that they can register new metrics
+pub(crate) fn spawn_thread(registry: MetricsRegistry) -> EmitterHandle {
+    EmitterHandle::Thread(std::thread::spawn(move || {
+        let emitter = registry.emitter.clone();
+        let schedule = registry.schedule.clone();
+        let mut deadline = schedule.first();
+        while !emitter.sleep(schedule.until(deadline)) {
+            registry.flush();
+            deadline = schedule.next(deadline);
+        }
+        // Final flush so whatever accumulated in the last window isn't lost
+        registry.flush();
+        emitter.mark_stopped();
+    }))
+}
+
+#[cfg(feature = "tokio")]
+impl Emitter {
+    fn is_stopping(&self) -> bool {
+        self.lock().stopping
+    }
+    /// Resolves once a shutdown is signalled. `notify_one` stores a permit, so a shutdown
+    /// signalled between checking `stopping` and awaiting isn't missed, a stale permit left
+    /// over from stopping a previous emitter is ignored.
+    async fn stop_requested(&self) {
+        while !self.is_stopping() {
+            self.notify.notified().await
+        }
+    }
+}
+
+/// Emission cycles may sleep on the rate limiter and write to blocking sockets,
+/// so they run on the blocking pool rather than on the runtime's workers.
+#[cfg(feature = "tokio")]
+async fn flush_blocking(registry: &MetricsRegistry) {
+    let registry = registry.clone();
+    let _ = tokio::task::spawn_blocking(move || registry.flush())
+        .await
+        .map_err(|err| tracing::debug!("Metrics emission task failed, was: {err}"));
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn spawn_task(
+    registry: MetricsRegistry,
+    runtime: &tokio::runtime::Handle,
+) -> EmitterHandle {
+    let schedule = registry.schedule.clone();
+    let start = tokio::time::Instant::now() + schedule.until(schedule.first());
+    EmitterHandle::Task(runtime.spawn(async move {
+        let emitter = registry.emitter.clone();
+        // Ticks stay on the first deadline's phase, skipping any that were missed
+        let mut interval = tokio::time::interval_at(start, schedule.period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = emitter.stop_requested() => break,
+            }
+            flush_blocking(&registry).await;
+        }
+        // Final flush so whatever accumulated in the last window isn't lost
+        flush_blocking(&registry).await;
+        emitter.mark_stopped();
+    }))
+}
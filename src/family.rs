@@ -0,0 +1,249 @@
+use std::{
+    borrow::Borrow,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+
+use crate::{
+    instrument::Instrument, MakeInstrument, Metric, MetricRegistrationError, MetricType,
+    MetricsRegistry,
+};
+
+pub type CountFamily = Family<MetricType::Count>;
+pub type GaugeFamily = Family<MetricType::Gauge>;
+pub type TimingCountFamily = Family<MetricType::TimingCount>;
+
+/// Tag values of a child, hashed and compared the same whether they're owned by the
+/// cache or borrowed from the caller so cache hits don't allocate.
+trait TagValues {
+    fn value_count(&self) -> usize;
+    fn value(&self, index: usize) -> &str;
+}
+
+impl TagValues for &[&str] {
+    fn value_count(&self) -> usize {
+        self.len()
+    }
+    fn value(&self, index: usize) -> &str {
+        self[index]
+    }
+}
+
+impl Hash for dyn TagValues + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.value_count());
+        for index in 0..self.value_count() {
+            self.value(index).hash(state);
+        }
+    }
+}
+
+impl PartialEq for dyn TagValues + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.value_count() == other.value_count()
+            && (0..self.value_count()).all(|index| self.value(index) == other.value(index))
+    }
+}
+
+impl Eq for dyn TagValues + '_ {}
+
+#[derive(Debug)]
+struct ChildKey(Box<[String]>);
+
+impl TagValues for ChildKey {
+    fn value_count(&self) -> usize {
+        self.0.len()
+    }
+    fn value(&self, index: usize) -> &str {
+        &self.0[index]
+    }
+}
+
+impl<'a> Borrow<dyn TagValues + 'a> for ChildKey {
+    fn borrow(&self) -> &(dyn TagValues + 'a) {
+        self
+    }
+}
+
+impl Hash for ChildKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as &dyn TagValues).hash(state)
+    }
+}
+
+impl PartialEq for ChildKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for ChildKey {}
+
+/// Instruments sharing a stat name, registered once with a list of tag keys, whose children
+/// are looked up by tag values like Prometheus vecs. The first [Family::with_values] call for a
+/// combination of values registers `key:value` tags merged with the metric's own tags, later
+/// calls return the cached child without allocating or touching the registry. Combinations past
+/// the registry's series limits get the `overflow:true` series and are looked up again every call
+/// instead of being cached.
+///
+/// Children are ordinary registry series, registering the same name and tags directly shares them.
+#[derive(Clone)]
+pub struct Family<T: MetricType::Impl + MakeInstrument> {
+    metric: Metric<T>,
+    tag_keys: Arc<[String]>,
+    registry: MetricsRegistry,
+    children: Arc<DashMap<ChildKey, T::InstrumentType>>,
+}
+
+impl<T> Family<T>
+where
+    T: MetricType::Impl + MakeInstrument + Clone,
+    <T as MakeInstrument>::InstrumentType: Into<Instrument> + Clone + 'static,
+{
+    pub fn register<M, I, S>(registry: &MetricsRegistry, metric: M, tag_keys: I) -> Self
+    where
+        M: Into<Metric<T>>,
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        Self {
+            metric: metric.into(),
+            tag_keys: tag_keys.into_iter().map(Into::into).collect(),
+            registry: registry.clone(),
+            children: Arc::new(DashMap::new()),
+        }
+    }
+    pub fn tag_keys(&self) -> &[String] {
+        &self.tag_keys
+    }
+    /// The child for `values`, given in the same order as the family's tag keys.
+    pub fn with_values(
+        &self,
+        values: &[&str],
+    ) -> Result<T::InstrumentType, MetricRegistrationError> {
+        if values.len() != self.tag_keys.len() {
+            return Err(MetricRegistrationError::TagValuesMismatch(
+                self.tag_keys.len(),
+                values.len(),
+            ));
+        }
+        if let Some(child) = self.children.get(&values as &dyn TagValues) {
+            return Ok(child.clone());
+        }
+        let mut metric_tags = self.metric.get_tags().clone();
+        metric_tags.extend(
+            self.tag_keys
+                .iter()
+                .zip(values)
+                .map(|(key, value)| format!("{key}:{value}")),
+        );
+        let metric = self.metric.clone().with_set_tags(metric_tags);
+        let instrument = metric.make_instrument();
+        let (child, admitted) = self.registry.admit_instrument(metric, instrument)?;
+        // Overflow children aren't cached so the cache stays within the series limits
+        if !admitted {
+            return Ok(child);
+        }
+        let key = ChildKey(values.iter().map(|value| value.to_string()).collect());
+        Ok(self.children.entry(key).or_insert(child).clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        registry::OVERFLOW_TAG,
+        testing::{recording_registry, RecordingSink},
+        EmissionMode, RegistryConfig,
+    };
+
+    #[test]
+    fn test_with_values() {
+        let (registry, sink) = recording_registry();
+        let requests = registry.register_count_family("gnort.requests", ["route", "status"]);
+        requests
+            .with_values(&["/users", "200"])
+            .unwrap()
+            .fetch_add(2);
+        requests
+            .with_values(&["/users", "200"])
+            .unwrap()
+            .increment();
+        requests
+            .with_values(&["/users", "500"])
+            .unwrap()
+            .increment();
+        assert_eq!(requests.children.len(), 2);
+        // Children are shared with direct registrations of the same series
+        registry
+            .register_count(
+                Metric::from("gnort.requests").with_tags(["route:/users", "status:500"]),
+            )
+            .unwrap()
+            .increment();
+        registry.flush();
+        sink.assert_count("gnort.requests", &["route:/users", "status:200"], 3);
+        sink.assert_count("gnort.requests", &["route:/users", "status:500"], 2);
+    }
+
+    #[test]
+    fn test_with_values_merges_tags() {
+        let (registry, sink) = recording_registry();
+        let latency = registry.register_timing_count_family(
+            Metric::from("gnort.latency").with_tags(["env:test"]),
+            ["route"],
+        );
+        latency
+            .with_values(&["/users"])
+            .unwrap()
+            .add_timing(&std::time::Duration::from_millis(5));
+        let depth = registry.register_gauge_family("gnort.queue.depth", ["queue"]);
+        depth.with_values(&["ingest"]).unwrap().swap(3.0);
+        registry.flush();
+        sink.assert_count("gnort.latency", &["env:test", "route:/users"], 1);
+        sink.assert_count("gnort.latency.time", &["env:test", "route:/users"], 5);
+        sink.assert_gauge("gnort.queue.depth", &["queue:ingest"], 3.0);
+    }
+
+    #[test]
+    fn test_overflow_children_not_cached() {
+        let sink = Arc::new(RecordingSink::default());
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_sink(sink.clone())
+                .with_emission(EmissionMode::Manual)
+                .with_max_series_per_metric(2),
+        );
+        let requests = registry.register_count_family("gnort.requests", ["user"]);
+        for user in 0..100 {
+            requests
+                .with_values(&[&user.to_string()])
+                .unwrap()
+                .increment();
+        }
+        assert_eq!(requests.children.len(), 2);
+        registry.flush();
+        sink.assert_count("gnort.requests", &["user:0"], 1);
+        sink.assert_count("gnort.requests", &[OVERFLOW_TAG], 98);
+    }
+
+    #[test]
+    fn test_with_values_errors() {
+        let (registry, _sink) = recording_registry();
+        let requests = registry.register_count_family("gnort.requests", ["route", "status"]);
+        assert!(matches!(
+            requests.with_values(&["/users"]),
+            Err(MetricRegistrationError::TagValuesMismatch(2, 1))
+        ));
+        registry
+            .register_gauge(Metric::from("gnort.requests").with_tags(["route:/", "status:200"]))
+            .unwrap();
+        assert!(matches!(
+            requests.with_values(&["/", "200"]),
+            Err(MetricRegistrationError::TypeMismatch(..))
+        ));
+    }
+}
@@ -1,25 +1,120 @@
 use std::{
+    collections::{BTreeSet, HashMap},
     future::Future,
+    hash::Hash,
     sync::{
-        atomic::{AtomicU64, AtomicUsize},
-        Arc,
+        atomic::{AtomicI64, AtomicU64, AtomicU8, AtomicUsize},
+        Arc, Mutex, MutexGuard, PoisonError,
     },
 };
 
-use dogstatsd::DogstatsdError;
+use once_cell::sync::OnceCell;
 
 use crate::{
-    GnortClient, MakeInstrument, MetricKey, MetricRegistrationError,
+    registry::{EmissionPolicy, SeriesBinding},
+    sink::{Sink, SinkResult},
+    sketch::{
+        hash_value, CountMinSketch, DDSketch, HyperLogLog, SketchError, DEFAULT_HLL_PRECISION,
+    },
+    MakeInstrument, MetricKey, MetricRegistrationError,
     MetricType::{self, Impl},
 };
 
 const DEFAULT_ORDERING: std::sync::atomic::Ordering = std::sync::atomic::Ordering::SeqCst;
+/// [Liveness::last_touched] of a series that was removed from its registry
+const EVICTED: u64 = u64::MAX;
+
+/// Tracks the registry generation (emission cycle) an instrument was last updated and emitted in,
+/// so idle series can be evicted and unchanged ones skipped, and puts evicted series back when a
+/// handle that's still held is updated.
+#[derive(Debug, Default)]
+pub(crate) struct Liveness {
+    last_touched: AtomicU64,
+    last_emitted: AtomicU64,
+    binding: OnceCell<SeriesBinding>,
+    // Overrides the registry's policy for this series
+    policy: Mutex<Option<EmissionPolicy>>,
+}
+
+impl Liveness {
+    /// Called on every update, `instrument` is only built to re-insert an evicted series.
+    fn touch<F: FnOnce() -> Instrument>(&self, instrument: F) {
+        let Some(binding) = self.binding.get() else {
+            return;
+        };
+        let generation = binding.generation();
+        // Only the first update in a window writes
+        if self.last_touched.load(DEFAULT_ORDERING) != generation
+            && self.last_touched.swap(generation, DEFAULT_ORDERING) == EVICTED
+        {
+            binding.reinsert(instrument());
+        }
+    }
+    /// Ties the instrument to the series it was inserted as, the first binding sticks.
+    pub(crate) fn bind(&self, binding: SeriesBinding) {
+        let generation = binding.generation();
+        self.last_touched.store(generation, DEFAULT_ORDERING);
+        self.last_emitted.store(generation, DEFAULT_ORDERING);
+        let _ = self.binding.set(binding);
+    }
+    /// The dirty flag: whether the series was updated in or after window `generation`.
+    fn is_dirty(&self, generation: u64) -> bool {
+        let last_touched = self.last_touched.load(DEFAULT_ORDERING);
+        last_touched != EVICTED && last_touched >= generation
+    }
+    /// Whether to emit window `generation` when `skippable` under `policy`, skippable windows
+    /// are still emitted once the policy's heartbeat is due.
+    fn should_emit(&self, skippable: bool, policy: &EmissionPolicy, generation: u64) -> bool {
+        let emit = !skippable
+            || policy.heartbeat.is_some_and(|windows| {
+                let last_emitted = self.last_emitted.load(DEFAULT_ORDERING);
+                generation.saturating_sub(last_emitted) >= u64::from(windows.get())
+            });
+        if emit {
+            self.last_emitted.store(generation, DEFAULT_ORDERING);
+        }
+        emit
+    }
+    fn policy(&self) -> Option<EmissionPolicy> {
+        *self.policy.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    pub(crate) fn set_policy(&self, policy: EmissionPolicy) {
+        *self.policy.lock().unwrap_or_else(PoisonError::into_inner) = Some(policy);
+    }
+    /// Marks the series evicted unless it was updated after generation `idle_since`.
+    pub(crate) fn try_evict(&self, idle_since: u64) -> bool {
+        let last_touched = self.last_touched.load(DEFAULT_ORDERING);
+        last_touched <= idle_since
+            && self
+                .last_touched
+                .compare_exchange(last_touched, EVICTED, DEFAULT_ORDERING, DEFAULT_ORDERING)
+                .is_ok()
+    }
+    /// Marks a series that was evicted as updated in the current window.
+    pub(crate) fn revive(&self) {
+        if let Some(binding) = self.binding.get() {
+            self.last_touched
+                .store(binding.generation(), DEFAULT_ORDERING);
+        }
+    }
+    pub(crate) fn evict(&self) {
+        self.last_touched.store(EVICTED, DEFAULT_ORDERING);
+    }
+    pub(crate) fn binding(&self) -> Option<&SeriesBinding> {
+        self.binding.get()
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct AtomicF64 {
     storage: Arc<AtomicU64>,
 }
 impl AtomicF64 {
+    pub(crate) fn new(value: f64) -> Self {
+        Self {
+            storage: Arc::new(AtomicU64::new(value.to_bits())),
+        }
+    }
     pub(crate) fn swap(&self, value: f64) -> f64 {
         let as_u64 = value.to_bits();
         f64::from_bits(self.storage.swap(as_u64, DEFAULT_ORDERING))
@@ -28,6 +123,31 @@ impl AtomicF64 {
         let as_u64 = self.storage.load(DEFAULT_ORDERING);
         f64::from_bits(as_u64)
     }
+    /// CAS loop applying `f` to the current value, returns the previous value.
+    fn fetch_update<F: Fn(f64) -> f64>(&self, f: F) -> f64 {
+        let mut current = self.storage.load(DEFAULT_ORDERING);
+        loop {
+            let new = f(f64::from_bits(current)).to_bits();
+            match self.storage.compare_exchange_weak(
+                current,
+                new,
+                DEFAULT_ORDERING,
+                DEFAULT_ORDERING,
+            ) {
+                Ok(previous) => return f64::from_bits(previous),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+    pub(crate) fn fetch_add(&self, value: f64) -> f64 {
+        self.fetch_update(|current| current + value)
+    }
+    pub(crate) fn fetch_min(&self, value: f64) -> f64 {
+        self.fetch_update(|current| current.min(value))
+    }
+    pub(crate) fn fetch_max(&self, value: f64) -> f64 {
+        self.fetch_update(|current| current.max(value))
+    }
 }
 
 pub type CountUnit = usize;
@@ -36,9 +156,14 @@ pub type GaugeUnit = f64;
 pub type GaugeValue = Arc<AtomicF64>;
 pub type TimingUnit = CountUnit;
 pub type TimingValue = CountValue;
+pub type UpDownUnit = i64;
+pub type UpDownValue = Arc<AtomicI64>;
 
 #[derive(Clone, Debug, Default)]
-pub struct Count(CountValue);
+pub struct Count {
+    value: CountValue,
+    liveness: Arc<Liveness>,
+}
 
 impl Count {
     const DEFAULT_VALUE: CountUnit = 0;
@@ -46,21 +171,154 @@ impl Count {
         self.fetch_add(1)
     }
     pub fn fetch_add(&self, val: usize) -> CountUnit {
-        self.0.fetch_add(val, DEFAULT_ORDERING)
+        self.liveness.touch(|| self.clone().into());
+        self.value.fetch_add(val, DEFAULT_ORDERING)
     }
     fn reset(&self) -> CountUnit {
-        self.0.swap(Self::DEFAULT_VALUE, DEFAULT_ORDERING)
+        self.value.swap(Self::DEFAULT_VALUE, DEFAULT_ORDERING)
+    }
+}
+
+/// Signed counter for values that go up and down, like in-flight requests or queue depth.
+/// Unlike [Count] it is not reset on emission, the running total is emitted as a gauge.
+#[derive(Clone, Debug, Default)]
+pub struct UpDownCounter {
+    value: UpDownValue,
+    liveness: Arc<Liveness>,
+}
+
+impl UpDownCounter {
+    pub fn increment(&self) -> UpDownUnit {
+        self.add(1)
+    }
+    pub fn decrement(&self) -> UpDownUnit {
+        self.sub(1)
+    }
+    pub fn add(&self, val: i64) -> UpDownUnit {
+        self.liveness.touch(|| self.clone().into());
+        self.value.fetch_add(val, DEFAULT_ORDERING)
+    }
+    pub fn sub(&self, val: i64) -> UpDownUnit {
+        self.liveness.touch(|| self.clone().into());
+        self.value.fetch_sub(val, DEFAULT_ORDERING)
+    }
+    pub fn load(&self) -> UpDownUnit {
+        self.value.load(DEFAULT_ORDERING)
+    }
+}
+
+/// How a [Gauge] combines the values recorded within one observation window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GaugeAggregation {
+    /// Most recent value wins and is carried over into the next window
+    #[default]
+    Last,
+    Max,
+    Min,
+    Sum,
+    Mean,
+}
+
+impl GaugeAggregation {
+    /// Value a window starts from before anything is recorded
+    fn identity(&self) -> GaugeUnit {
+        match self {
+            GaugeAggregation::Max => f64::NEG_INFINITY,
+            GaugeAggregation::Min => f64::INFINITY,
+            GaugeAggregation::Last | GaugeAggregation::Sum | GaugeAggregation::Mean => 0.0,
+        }
     }
 }
 
+/// Gauge aggregated per window according to its [GaugeAggregation], chosen at registration
+/// with [register_gauge_with_aggregation](crate::MetricsRegistry::register_gauge_with_aggregation).
+/// For `Mean` the value holds the running sum and `count` the number of recorded values.
 #[derive(Clone, Debug, Default)]
-pub struct Gauge(GaugeValue);
+pub struct Gauge {
+    value: GaugeValue,
+    count: CountValue,
+    aggregation: GaugeAggregation,
+    liveness: Arc<Liveness>,
+}
 impl Gauge {
+    pub fn new(aggregation: GaugeAggregation) -> Self {
+        Self {
+            value: Arc::new(AtomicF64::new(aggregation.identity())),
+            count: CountValue::default(),
+            aggregation,
+            liveness: Arc::default(),
+        }
+    }
+    pub fn aggregation(&self) -> GaugeAggregation {
+        self.aggregation
+    }
+    /// Restarts the window with `value` as its only recorded value and returns the previous
+    /// aggregated value, like [Gauge::load]. Prefer [Gauge::record] for gauges that aren't
+    /// [GaugeAggregation::Last].
     pub fn swap(&self, value: f64) -> GaugeUnit {
-        self.0.swap(value)
+        self.liveness.touch(|| self.clone().into());
+        let count = self.count.swap(1, DEFAULT_ORDERING);
+        let previous = self.value.swap(value);
+        match self.aggregation {
+            GaugeAggregation::Mean if count == 0 => 0.0,
+            GaugeAggregation::Mean => previous / count as f64,
+            _ => previous,
+        }
+    }
+    /// Records `value` according to the gauge's [GaugeAggregation].
+    pub fn record(&self, value: f64) {
+        self.liveness.touch(|| self.clone().into());
+        match self.aggregation {
+            GaugeAggregation::Last => {
+                self.value.swap(value);
+            }
+            GaugeAggregation::Max => {
+                self.value.fetch_max(value);
+            }
+            GaugeAggregation::Min => {
+                self.value.fetch_min(value);
+            }
+            GaugeAggregation::Sum | GaugeAggregation::Mean => {
+                self.value.fetch_add(value);
+            }
+        }
+        self.count.fetch_add(1, DEFAULT_ORDERING);
     }
+    /// Aggregated value of the current window.
     pub fn load(&self) -> GaugeUnit {
-        self.0.load()
+        match self.aggregation {
+            GaugeAggregation::Mean => {
+                let count = self.count.load(DEFAULT_ORDERING);
+                if count == 0 {
+                    0.0
+                } else {
+                    self.value.load() / count as f64
+                }
+            }
+            _ => self.value.load(),
+        }
+    }
+    /// Returns the window's aggregated value, `None` if a Max/Min/Mean window saw no values.
+    /// `Last` gauges keep their value, every other mode starts the next window over.
+    pub(crate) fn reset(&self) -> Option<GaugeUnit> {
+        let identity = self.aggregation.identity();
+        match self.aggregation {
+            GaugeAggregation::Last => Some(self.value.load()),
+            GaugeAggregation::Sum => {
+                self.count.swap(0, DEFAULT_ORDERING);
+                Some(self.value.swap(identity))
+            }
+            GaugeAggregation::Max | GaugeAggregation::Min => {
+                let count = self.count.swap(0, DEFAULT_ORDERING);
+                let value = self.value.swap(identity);
+                (count > 0).then_some(value)
+            }
+            GaugeAggregation::Mean => {
+                let count = self.count.swap(0, DEFAULT_ORDERING);
+                let sum = self.value.swap(identity);
+                (count > 0).then(|| sum / count as f64)
+            }
+        }
     }
 }
 
@@ -78,6 +336,7 @@ pub struct TimingCount {
     sum: TimingValue,
     count: TimingValue,
     unit: UnitOfTime,
+    liveness: Arc<Liveness>,
 }
 
 impl TimingCount {
@@ -100,6 +359,7 @@ impl TimingCount {
         duration: &std::time::Duration,
         count: TimingUnit,
     ) -> (TimingUnit, TimingUnit) {
+        self.liveness.touch(|| self.clone().into());
         let duration_sum = Self::duration_via_unit(self.unit, duration);
         let sum = self.sum.fetch_add(duration_sum, DEFAULT_ORDERING);
         let count = self.count.fetch_add(count, DEFAULT_ORDERING);
@@ -135,6 +395,416 @@ impl TimingCount {
         let duration = end_time - start_time;
         (result, duration)
     }
+    /// Starts timing the current scope, the elapsed time is recorded when the guard is dropped,
+    /// including when the scope exits early via `return` or `?`.
+    pub fn start_timer(&self) -> TimerGuard {
+        TimerGuard {
+            timing_count: Some(self.clone()),
+            start_time: std::time::Instant::now(),
+        }
+    }
+}
+
+/// Returned by [TimingCount::start_timer], records the elapsed time into the [TimingCount] on drop.
+#[must_use = "the timer records as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct TimerGuard {
+    timing_count: Option<TimingCount>,
+    start_time: std::time::Instant,
+}
+
+impl TimerGuard {
+    /// Time elapsed since the timer was started.
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.start_time.elapsed()
+    }
+    /// Stops the timer and records the elapsed time now rather than on drop.
+    pub fn observe_duration(mut self) -> std::time::Duration {
+        let duration = self.elapsed();
+        if let Some(timing_count) = self.timing_count.take() {
+            let _ = timing_count.add_timing(&duration);
+        }
+        duration
+    }
+    /// Stops the timer without recording anything.
+    pub fn discard(mut self) {
+        self.timing_count = None;
+    }
+}
+
+impl Drop for TimerGuard {
+    fn drop(&mut self) {
+        if let Some(timing_count) = self.timing_count.take() {
+            let _ = timing_count.add_timing(&self.start_time.elapsed());
+        }
+    }
+}
+
+// Histogram buckets are keyed on the exponent and the top HISTOGRAM_MANTISSA_BITS bits of the
+// mantissa of the recorded f64, so every bucket is at most 1/16th (6.25%) wider than its lower bound.
+// The tracked exponent range covers roughly 6e-8 to 1.1e12, values outside of it are clamped
+// into the first/last bucket. min and max are tracked exactly so quantiles are clamped to them.
+const HISTOGRAM_MANTISSA_BITS: u32 = 4;
+const HISTOGRAM_KEY_SHIFT: u32 = 52 - HISTOGRAM_MANTISSA_BITS;
+const HISTOGRAM_MIN_EXPONENT: i64 = -24;
+const HISTOGRAM_MAX_EXPONENT: i64 = 40;
+const HISTOGRAM_MIN_KEY: u64 = ((1023 + HISTOGRAM_MIN_EXPONENT) as u64) << HISTOGRAM_MANTISSA_BITS;
+const HISTOGRAM_BUCKETS: usize =
+    ((HISTOGRAM_MAX_EXPONENT - HISTOGRAM_MIN_EXPONENT) as usize) << HISTOGRAM_MANTISSA_BITS;
+/// Quantiles emitted by [Histogram] and [Distribution] alongside min/max/avg/count.
+pub const SUMMARY_QUANTILES: [(&str, f64); 3] = [("p50", 0.50), ("p95", 0.95), ("p99", 0.99)];
+
+/// Snapshot of a [Histogram] taken when the observation window is reset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistogramSummary {
+    pub count: CountUnit,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    buckets: Vec<CountUnit>,
+}
+
+impl HistogramSummary {
+    pub fn avg(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+    /// Estimated value at quantile `q` (0.0..=1.0), within one bucket's width of the true value.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let total: CountUnit = self.buckets.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as CountUnit).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                // The first and last buckets catch out-of-range values, and necessarily hold min/max
+                return match index {
+                    0 => self.min,
+                    index if index == HISTOGRAM_BUCKETS - 1 => self.max,
+                    index => Histogram::bucket_midpoint(index).clamp(self.min, self.max),
+                };
+            }
+        }
+        self.max
+    }
+}
+
+/// Client-side aggregated histogram. Values are recorded lock-free into fixed log-linear buckets
+/// and summarized as min/max/avg/count and [SUMMARY_QUANTILES] gauges on every emission.
+///
+/// The window's count is taken from the swapped buckets, so it always agrees with the quantiles.
+/// sum, min and max are swapped separately, a `record` racing with the reset may have its value
+/// counted in one window and its sum/min/max in the next.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    sum: AtomicF64,
+    min: AtomicF64,
+    max: AtomicF64,
+    buckets: Arc<[AtomicUsize]>,
+    unit: UnitOfTime,
+    liveness: Arc<Liveness>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            sum: AtomicF64::default(),
+            min: AtomicF64::new(f64::INFINITY),
+            max: AtomicF64::new(f64::NEG_INFINITY),
+            buckets: (0..HISTOGRAM_BUCKETS)
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            unit: UnitOfTime::default(),
+            liveness: Arc::default(),
+        }
+    }
+}
+
+impl Histogram {
+    /// Unit used by [Histogram::record_duration]
+    pub fn with_unit(self, unit: UnitOfTime) -> Self {
+        Self { unit, ..self }
+    }
+    fn bucket_index(value: f64) -> usize {
+        if value.is_nan() || value <= 0.0 {
+            return 0;
+        }
+        let key = value.to_bits() >> HISTOGRAM_KEY_SHIFT;
+        (key.saturating_sub(HISTOGRAM_MIN_KEY) as usize).min(HISTOGRAM_BUCKETS - 1)
+    }
+    fn bucket_midpoint(index: usize) -> f64 {
+        let key = HISTOGRAM_MIN_KEY + index as u64;
+        let lower = f64::from_bits(key << HISTOGRAM_KEY_SHIFT);
+        let upper = f64::from_bits((key + 1) << HISTOGRAM_KEY_SHIFT);
+        (lower + upper) / 2.0
+    }
+    pub fn record(&self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.liveness.touch(|| self.clone().into());
+        self.buckets[Self::bucket_index(value)].fetch_add(1, DEFAULT_ORDERING);
+        self.sum.fetch_add(value);
+        self.min.fetch_min(value);
+        self.max.fetch_max(value);
+    }
+    pub fn record_duration(&self, duration: &std::time::Duration) {
+        let value = TimingCount::duration_via_unit(self.unit, duration);
+        self.record(value as f64)
+    }
+    pub(crate) fn reset(&self) -> HistogramSummary {
+        let buckets: Vec<CountUnit> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.swap(0, DEFAULT_ORDERING))
+            .collect();
+        let mut summary = HistogramSummary {
+            count: buckets.iter().sum(),
+            sum: self.sum.swap(0.0),
+            min: self.min.swap(f64::INFINITY),
+            max: self.max.swap(f64::NEG_INFINITY),
+            buckets,
+        };
+        // A racing record's min/max landed in the next window, fall back to its bucket
+        if summary.count > 0 && summary.min > summary.max {
+            let mut occupied = (summary.buckets.iter().enumerate())
+                .filter(|(_, bucket)| **bucket > 0)
+                .map(|(index, _)| Self::bucket_midpoint(index));
+            summary.min = occupied.next().unwrap_or_default();
+            summary.max = occupied.next_back().unwrap_or(summary.min);
+        }
+        summary
+    }
+}
+
+/// How a [Distribution] reports each observation window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistributionEmission {
+    /// min/max/avg/count and [SUMMARY_QUANTILES] computed client-side and sent as gauges
+    #[default]
+    Quantiles,
+    /// Bin values sent as DogStatsD distribution (`|d`) values, packed several per datagram,
+    /// so the agent can aggregate percentiles across hosts.
+    Distribution,
+}
+
+#[derive(Debug, Default)]
+struct DistributionState {
+    sketch: DDSketch,
+    emission: DistributionEmission,
+}
+
+/// Client-side aggregated distribution backed by a [DDSketch]. Sketches with the same
+/// relative accuracy can be merged in, so hot paths can record into a thread-local
+/// [DDSketch] and periodically fold it into the shared [Distribution].
+#[derive(Clone, Debug, Default)]
+pub struct Distribution {
+    state: Arc<Mutex<DistributionState>>,
+    unit: UnitOfTime,
+    liveness: Arc<Liveness>,
+}
+
+impl Distribution {
+    /// Unit used by [Distribution::record_duration]
+    pub fn with_unit(self, unit: UnitOfTime) -> Self {
+        Self { unit, ..self }
+    }
+    /// Changes how the registry emits this distribution, shared by every clone of the instrument.
+    pub fn set_emission(&self, emission: DistributionEmission) {
+        self.lock().emission = emission;
+    }
+    fn lock(&self) -> MutexGuard<'_, DistributionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn record(&self, value: f64) {
+        self.liveness.touch(|| self.clone().into());
+        self.lock().sketch.add(value)
+    }
+    pub fn record_duration(&self, duration: &std::time::Duration) {
+        let value = TimingCount::duration_via_unit(self.unit, duration);
+        self.record(value as f64)
+    }
+    /// Estimated value at quantile `q` for the current observation window.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        self.lock().sketch.quantile(q)
+    }
+    pub fn merge(&self, sketch: &DDSketch) -> Result<(), SketchError> {
+        self.liveness.touch(|| self.clone().into());
+        self.lock().sketch.merge(sketch)
+    }
+    /// Copy of the sketch for the current observation window.
+    pub fn snapshot(&self) -> DDSketch {
+        self.lock().sketch.clone()
+    }
+    pub(crate) fn reset(&self) -> (DDSketch, DistributionEmission) {
+        let mut state = self.lock();
+        let fresh = DDSketch::new(state.sketch.relative_accuracy());
+        (std::mem::replace(&mut state.sketch, fresh), state.emission)
+    }
+}
+
+/// Default number of keys [TopK] emits per observation window.
+pub const DEFAULT_TOP_K: usize = 10;
+/// Default tag key [TopK] uses for the observed key.
+pub const DEFAULT_TOP_K_TAG: &str = "key";
+
+#[derive(Debug)]
+struct TopKState {
+    sketch: CountMinSketch,
+    candidates: HashMap<Arc<str>, u64>,
+    // The candidates ordered by estimate, so the lightest one is found in O(log k) on eviction
+    by_estimate: BTreeSet<(u64, Arc<str>)>,
+    k: usize,
+    tag_key: String,
+}
+
+impl Default for TopKState {
+    fn default() -> Self {
+        Self {
+            sketch: CountMinSketch::default(),
+            candidates: HashMap::with_capacity(DEFAULT_TOP_K + 1),
+            by_estimate: BTreeSet::new(),
+            k: DEFAULT_TOP_K,
+            tag_key: DEFAULT_TOP_K_TAG.to_string(),
+        }
+    }
+}
+
+impl TopKState {
+    fn insert(&mut self, key: Arc<str>, estimate: u64) {
+        if let Some(previous) = self.candidates.insert(key.clone(), estimate) {
+            self.by_estimate.remove(&(previous, key.clone()));
+        }
+        self.by_estimate.insert((estimate, key));
+    }
+}
+
+/// Heavy-hitter tracking for high-cardinality keys. Frequencies are estimated in a fixed-memory
+/// [CountMinSketch] and only the K most frequent keys are kept, so each window emits at most K
+/// count series tagged with `key:<key>` no matter how many distinct keys were observed.
+///
+/// The emitted counts are estimates, not exact counts. A count-min sketch never undercounts, and
+/// with the default [DEFAULT_CMS_WIDTH](crate::sketch::DEFAULT_CMS_WIDTH) x
+/// [DEFAULT_CMS_DEPTH](crate::sketch::DEFAULT_CMS_DEPTH) sketch each key overcounts by at most
+/// `e / width` (~0.13%) of the window's total observations with probability `1 - e^-depth` (~99.3%).
+/// Counts of rare keys in busy windows can be mostly error, compare them against that bound rather
+/// than reading them as exact.
+#[derive(Clone, Debug, Default)]
+pub struct TopK {
+    state: Arc<Mutex<TopKState>>,
+    liveness: Arc<Liveness>,
+}
+
+impl TopK {
+    fn lock(&self) -> MutexGuard<'_, TopKState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Changes how many keys are emitted per window, shared by every clone of the instrument.
+    pub fn set_k(&self, k: usize) {
+        self.lock().k = k.max(1);
+    }
+    /// Changes the tag key the observed keys are emitted under, defaults to [DEFAULT_TOP_K_TAG].
+    pub fn set_tag_key<S: Into<String>>(&self, tag_key: S) {
+        self.lock().tag_key = tag_key.into();
+    }
+    pub fn observe(&self, key: &str) -> u64 {
+        self.observe_n(key, 1)
+    }
+    /// Adds `count` observations of `key`, returns the key's estimated count this window.
+    pub fn observe_n(&self, key: &str, count: u64) -> u64 {
+        self.liveness.touch(|| self.clone().into());
+        let mut state = self.lock();
+        let estimate = state.sketch.add(key, count);
+        let candidate = state
+            .candidates
+            .get_key_value(key)
+            .map(|(key, _)| key.clone());
+        if let Some(candidate) = candidate {
+            state.insert(candidate, estimate);
+        } else if state.candidates.len() < state.k {
+            state.insert(key.into(), estimate);
+        } else if state
+            .by_estimate
+            .first()
+            .is_some_and(|(lightest, _)| *lightest < estimate)
+        {
+            if let Some((_, evicted)) = state.by_estimate.pop_first() {
+                state.candidates.remove(&evicted);
+            }
+            state.insert(key.into(), estimate);
+        }
+        estimate
+    }
+    /// The current window's heaviest keys and their estimated counts, most frequent first.
+    pub fn top(&self) -> Vec<(String, u64)> {
+        Self::sorted(self.lock().candidates.clone())
+    }
+    fn sorted(candidates: HashMap<Arc<str>, u64>) -> Vec<(String, u64)> {
+        let mut top: Vec<_> = (candidates.into_iter())
+            .map(|(key, estimate)| (key.to_string(), estimate))
+            .collect();
+        top.sort_by(|(left_key, left), (right_key, right)| {
+            right.cmp(left).then_with(|| left_key.cmp(right_key))
+        });
+        top
+    }
+    pub(crate) fn reset(&self) -> (Vec<(String, u64)>, String) {
+        let mut state = self.lock();
+        state.sketch.clear();
+        state.by_estimate.clear();
+        let candidates = std::mem::take(&mut state.candidates);
+        (Self::sorted(candidates), state.tag_key.clone())
+    }
+}
+
+/// Unique value counting via a lock-free [HyperLogLog]. Registers are updated with atomic
+/// `fetch_max` so observing never blocks, and the cardinality estimate for each window is
+/// emitted as a gauge rather than sending every value to the agent.
+#[derive(Clone, Debug)]
+pub struct Set {
+    precision: u8,
+    registers: Arc<[AtomicU8]>,
+    liveness: Arc<Liveness>,
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self {
+            precision: DEFAULT_HLL_PRECISION,
+            registers: (0..1 << DEFAULT_HLL_PRECISION)
+                .map(|_| AtomicU8::new(0))
+                .collect(),
+            liveness: Arc::default(),
+        }
+    }
+}
+
+impl Set {
+    pub fn observe<T: Hash + ?Sized>(&self, value: &T) {
+        self.liveness.touch(|| self.clone().into());
+        let (index, rank) = HyperLogLog::register_for(self.precision, hash_value(value));
+        self.registers[index].fetch_max(rank, DEFAULT_ORDERING);
+    }
+    fn sketch_with<F: Fn(&AtomicU8) -> u8>(&self, f: F) -> HyperLogLog {
+        HyperLogLog::from_registers(self.registers.iter().map(f).collect())
+    }
+    /// Estimated number of distinct values observed in the current window.
+    pub fn estimate(&self) -> f64 {
+        self.snapshot().estimate()
+    }
+    /// Copy of the sketch for the current window, for merging across registries or hosts.
+    pub fn snapshot(&self) -> HyperLogLog {
+        self.sketch_with(|register| register.load(DEFAULT_ORDERING))
+    }
+    pub(crate) fn reset(&self) -> HyperLogLog {
+        self.sketch_with(|register| register.swap(0, DEFAULT_ORDERING))
+    }
 }
 
 impl From<Count> for Instrument {
@@ -155,11 +825,46 @@ impl From<Gauge> for Instrument {
     }
 }
 
+impl From<Histogram> for Instrument {
+    fn from(histogram: Histogram) -> Self {
+        Self::Histogram(histogram)
+    }
+}
+
+impl From<Distribution> for Instrument {
+    fn from(distribution: Distribution) -> Self {
+        Self::Distribution(distribution)
+    }
+}
+
+impl From<TopK> for Instrument {
+    fn from(top_k: TopK) -> Self {
+        Self::TopK(top_k)
+    }
+}
+
+impl From<Set> for Instrument {
+    fn from(set: Set) -> Self {
+        Self::Set(set)
+    }
+}
+
+impl From<UpDownCounter> for Instrument {
+    fn from(up_down_counter: UpDownCounter) -> Self {
+        Self::UpDownCounter(up_down_counter)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Instrument {
     Count(Count),
     Gauge(Gauge),
     TimingCount(TimingCount),
+    Histogram(Histogram),
+    Distribution(Distribution),
+    TopK(TopK),
+    Set(Set),
+    UpDownCounter(UpDownCounter),
 }
 
 impl Instrument {
@@ -172,29 +877,142 @@ impl Instrument {
     pub(crate) fn timing_count() -> TimingCount {
         TimingCount::default()
     }
+    pub(crate) fn histogram() -> Histogram {
+        Histogram::default()
+    }
+    pub(crate) fn distribution() -> Distribution {
+        Distribution::default()
+    }
+    pub(crate) fn top_k() -> TopK {
+        TopK::default()
+    }
+    pub(crate) fn set() -> Set {
+        Set::default()
+    }
+    pub(crate) fn up_down_counter() -> UpDownCounter {
+        UpDownCounter::default()
+    }
+    pub(crate) fn liveness(&self) -> &Arc<Liveness> {
+        match self {
+            Instrument::Count(count) => &count.liveness,
+            Instrument::Gauge(gauge) => &gauge.liveness,
+            Instrument::TimingCount(timing_count) => &timing_count.liveness,
+            Instrument::Histogram(histogram) => &histogram.liveness,
+            Instrument::Distribution(distribution) => &distribution.liveness,
+            Instrument::TopK(top_k) => &top_k.liveness,
+            Instrument::Set(set) => &set.liveness,
+            Instrument::UpDownCounter(up_down_counter) => &up_down_counter.liveness,
+        }
+    }
+    /// Emits window `generation`, skipping zero and unchanged values as `policy` allows unless
+    /// the series has its own policy.
     pub(crate) fn emit(
         &self,
-        client: &GnortClient,
+        sink: &dyn Sink,
         metric_key: &MetricKey,
-    ) -> Result<(), DogstatsdError> {
+        policy: &EmissionPolicy,
+        generation: u64,
+    ) -> SinkResult {
         let name = metric_key.get_name();
+        let tags: Vec<&str> = metric_key.get_tags().iter().map(String::as_str).collect();
+        let tags = tags.as_slice();
+        let liveness = self.liveness();
+        let policy = liveness.policy().unwrap_or(*policy);
+        let unchanged = policy.skip_unchanged_gauges && !liveness.is_dirty(generation);
+        let should_emit = |skippable: bool| liveness.should_emit(skippable, &policy, generation);
         match self {
             Instrument::Count(count) => {
                 // Reset the count and get the final value before emitting
                 let metric_value = count.reset();
-                client.count(name, metric_value as i64, metric_key.get_tags())
-            }
-            Instrument::Gauge(gauge) => {
-                let val_str = gauge.load().to_string();
-                client.gauge(name, &val_str, metric_key.get_tags())
+                if !should_emit(policy.skip_zero_counts && metric_value == 0) {
+                    return Ok(());
+                }
+                sink.count(name, metric_value as i64, tags)
             }
+            Instrument::Gauge(gauge) => match gauge.reset() {
+                Some(value) if should_emit(unchanged) => sink.gauge(name, value, tags),
+                _ => Ok(()),
+            },
             Instrument::TimingCount(timing_count) => {
                 let (sum, count) = timing_count.reset();
-                let sum_name = format!("{}.time", name);
-                let count_name = name;
-                let tags = metric_key.get_tags();
-                client.count(sum_name, sum as i64, tags)?;
-                client.count(count_name, count as i64, tags)
+                if !should_emit(policy.skip_zero_counts && sum == 0 && count == 0) {
+                    return Ok(());
+                }
+                sink.timing_count(name, sum as i64, count as i64, tags)
+            }
+            Instrument::Histogram(histogram) => {
+                let summary = histogram.reset();
+                if !should_emit(policy.skip_zero_counts && summary.count == 0) {
+                    return Ok(());
+                }
+                sink.gauge(&format!("{}.count", name), summary.count as f64, tags)?;
+                // min/max/quantiles are meaningless for an empty window
+                if summary.count == 0 {
+                    return Ok(());
+                }
+                sink.gauge(&format!("{}.min", name), summary.min, tags)?;
+                sink.gauge(&format!("{}.max", name), summary.max, tags)?;
+                sink.gauge(&format!("{}.avg", name), summary.avg(), tags)?;
+                for (suffix, q) in SUMMARY_QUANTILES {
+                    sink.gauge(&format!("{}.{}", name, suffix), summary.quantile(q), tags)?;
+                }
+                Ok(())
+            }
+            Instrument::Distribution(distribution) => {
+                let (sketch, emission) = distribution.reset();
+                match emission {
+                    DistributionEmission::Quantiles => {
+                        if !should_emit(policy.skip_zero_counts && sketch.count() == 0) {
+                            return Ok(());
+                        }
+                        sink.gauge(&format!("{}.count", name), sketch.count() as f64, tags)?;
+                        let (Some(min), Some(max), Some(avg)) =
+                            (sketch.min(), sketch.max(), sketch.avg())
+                        else {
+                            return Ok(());
+                        };
+                        sink.gauge(&format!("{}.min", name), min, tags)?;
+                        sink.gauge(&format!("{}.max", name), max, tags)?;
+                        sink.gauge(&format!("{}.avg", name), avg, tags)?;
+                        for (suffix, q) in SUMMARY_QUANTILES {
+                            if let Some(value) = sketch.quantile(q) {
+                                sink.gauge(&format!("{}.{}", name, suffix), value, tags)?;
+                            }
+                        }
+                        Ok(())
+                    }
+                    DistributionEmission::Distribution => {
+                        let bins: Vec<(f64, u64)> = sketch.bins().collect();
+                        if bins.is_empty() {
+                            return Ok(());
+                        }
+                        sink.weighted_distribution(name, &bins, tags)
+                    }
+                }
+            }
+            Instrument::TopK(top_k) => {
+                let (top, tag_key) = top_k.reset();
+                for (key, count) in top {
+                    let key_tag = format!("{}:{}", tag_key, key);
+                    let mut key_tags = tags.to_vec();
+                    key_tags.push(&key_tag);
+                    sink.count(name, count as i64, &key_tags)?;
+                }
+                Ok(())
+            }
+            Instrument::Set(set) => {
+                let estimate = set.reset().estimate().round();
+                if !should_emit(unchanged) {
+                    return Ok(());
+                }
+                sink.gauge(name, estimate, tags)
+            }
+            Instrument::UpDownCounter(up_down_counter) => {
+                // Not reset, the running total carries over into the next window
+                if !should_emit(unchanged) {
+                    return Ok(());
+                }
+                sink.gauge(name, up_down_counter.load() as f64, tags)
             }
         }
     }
@@ -247,6 +1065,77 @@ impl Instrument {
                     )),
                 }
             }
+            Instrument::Histogram(histogram) => {
+                let any_histogram = Box::new(histogram.clone()) as Box<dyn core::any::Any>;
+                let downcasted: Result<
+                    Box<<T as MakeInstrument>::InstrumentType>,
+                    Box<dyn core::any::Any + 'static>,
+                > = any_histogram.downcast();
+                match downcasted {
+                    Ok(downcasted) => Ok(*downcasted),
+                    Err(_) => Err(MetricRegistrationError::TypeMismatch(
+                        MetricType::Histogram::name(),
+                        Instrument::Histogram(histogram.to_owned()),
+                    )),
+                }
+            }
+            Instrument::Distribution(distribution) => {
+                let any_distribution = Box::new(distribution.clone()) as Box<dyn core::any::Any>;
+                let downcasted: Result<
+                    Box<<T as MakeInstrument>::InstrumentType>,
+                    Box<dyn core::any::Any + 'static>,
+                > = any_distribution.downcast();
+                match downcasted {
+                    Ok(downcasted) => Ok(*downcasted),
+                    Err(_) => Err(MetricRegistrationError::TypeMismatch(
+                        MetricType::Distribution::name(),
+                        Instrument::Distribution(distribution.to_owned()),
+                    )),
+                }
+            }
+            Instrument::TopK(top_k) => {
+                let any_top_k = Box::new(top_k.clone()) as Box<dyn core::any::Any>;
+                let downcasted: Result<
+                    Box<<T as MakeInstrument>::InstrumentType>,
+                    Box<dyn core::any::Any + 'static>,
+                > = any_top_k.downcast();
+                match downcasted {
+                    Ok(downcasted) => Ok(*downcasted),
+                    Err(_) => Err(MetricRegistrationError::TypeMismatch(
+                        MetricType::TopK::name(),
+                        Instrument::TopK(top_k.to_owned()),
+                    )),
+                }
+            }
+            Instrument::Set(set) => {
+                let any_set = Box::new(set.clone()) as Box<dyn core::any::Any>;
+                let downcasted: Result<
+                    Box<<T as MakeInstrument>::InstrumentType>,
+                    Box<dyn core::any::Any + 'static>,
+                > = any_set.downcast();
+                match downcasted {
+                    Ok(downcasted) => Ok(*downcasted),
+                    Err(_) => Err(MetricRegistrationError::TypeMismatch(
+                        MetricType::Set::name(),
+                        Instrument::Set(set.to_owned()),
+                    )),
+                }
+            }
+            Instrument::UpDownCounter(up_down_counter) => {
+                let any_up_down_counter =
+                    Box::new(up_down_counter.clone()) as Box<dyn core::any::Any>;
+                let downcasted: Result<
+                    Box<<T as MakeInstrument>::InstrumentType>,
+                    Box<dyn core::any::Any + 'static>,
+                > = any_up_down_counter.downcast();
+                match downcasted {
+                    Ok(downcasted) => Ok(*downcasted),
+                    Err(_) => Err(MetricRegistrationError::TypeMismatch(
+                        MetricType::UpDownCounter::name(),
+                        Instrument::UpDownCounter(up_down_counter.to_owned()),
+                    )),
+                }
+            }
         }
     }
 }
@@ -293,6 +1182,275 @@ mod test {
         let downcasted = make().downcast::<MetricType::Count>();
         assert!(downcasted.is_err());
     }
+    #[test]
+    fn test_histogram() {
+        let make = || Instrument::Histogram(Histogram::default());
+        // Matching type should succeed
+        let downcasted = make().downcast::<MetricType::Histogram>();
+        assert!(downcasted.is_ok());
+        // Non-matching types should fail
+        let downcasted = make().downcast::<MetricType::TimingCount>();
+        assert!(downcasted.is_err());
+        let downcasted = make().downcast::<MetricType::Count>();
+        assert!(downcasted.is_err());
+    }
+
+    #[test]
+    fn test_histogram_summary() {
+        let histogram = Histogram::default();
+        for value in 1..=1000 {
+            histogram.record(value as f64);
+        }
+        let summary = histogram.reset();
+        assert_eq!(summary.count, 1000);
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.max, 1000.0);
+        assert_eq!(summary.avg(), 500.5);
+        for (q, expected) in [(0.5, 500.0), (0.95, 950.0), (0.99, 990.0)] {
+            let estimate = summary.quantile(q);
+            assert!(
+                (estimate - expected).abs() / expected < 0.0625,
+                "q{q} estimate {estimate} too far from {expected}"
+            );
+        }
+        // Resetting starts a fresh window
+        let summary = histogram.reset();
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.quantile(0.99), 0.0);
+    }
+
+    #[test]
+    fn test_histogram_clamps_out_of_range_values() {
+        let histogram = Histogram::default();
+        histogram.record(0.0);
+        histogram.record(-5.0);
+        histogram.record(1e300);
+        histogram.record(f64::NAN);
+        let summary = histogram.reset();
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.min, -5.0);
+        assert_eq!(summary.max, 1e300);
+        assert_eq!(summary.quantile(0.5), -5.0);
+        assert_eq!(summary.quantile(1.0), 1e300);
+    }
+
+    #[test]
+    fn test_histogram_reset_racing_record() {
+        let histogram = Histogram::default();
+        // A record whose bucket lands before the reset and its min/max after it
+        histogram.buckets[Histogram::bucket_index(10.0)].fetch_add(1, DEFAULT_ORDERING);
+        let summary = histogram.reset();
+        assert_eq!(summary.count, 1);
+        assert!(summary.min <= summary.max);
+        assert!((summary.quantile(0.5) - 10.0).abs() / 10.0 < 0.0625);
+    }
+
+    #[test]
+    fn test_distribution() {
+        let make = || Instrument::Distribution(Distribution::default());
+        // Matching type should succeed
+        let downcasted = make().downcast::<MetricType::Distribution>();
+        assert!(downcasted.is_ok());
+        // Non-matching types should fail
+        let downcasted = make().downcast::<MetricType::Histogram>();
+        assert!(downcasted.is_err());
+        let downcasted = make().downcast::<MetricType::Count>();
+        assert!(downcasted.is_err());
+    }
+
+    #[test]
+    fn test_distribution_merge_and_reset() {
+        let distribution = Distribution::default();
+        let handles: Vec<_> = (0..4)
+            .map(|thread| {
+                let distribution = distribution.clone();
+                std::thread::spawn(move || {
+                    let mut local = DDSketch::default();
+                    for value in 1..=250 {
+                        local.add((thread * 250 + value) as f64);
+                    }
+                    distribution.merge(&local).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let p99 = distribution.quantile(0.99).unwrap();
+        assert!((p99 - 990.0).abs() / 990.0 <= 0.01);
+        distribution.set_emission(DistributionEmission::Distribution);
+        let (sketch, emission) = distribution.reset();
+        assert_eq!(sketch.count(), 1000);
+        assert_eq!(emission, DistributionEmission::Distribution);
+        // The window resets but the emission mode sticks
+        assert_eq!(distribution.quantile(0.5), None);
+        assert_eq!(distribution.reset().1, DistributionEmission::Distribution);
+    }
+
+    #[test]
+    fn test_top_k() {
+        let make = || Instrument::TopK(TopK::default());
+        // Matching type should succeed
+        let downcasted = make().downcast::<MetricType::TopK>();
+        assert!(downcasted.is_ok());
+        // Non-matching types should fail
+        let downcasted = make().downcast::<MetricType::Count>();
+        assert!(downcasted.is_err());
+    }
+
+    #[test]
+    fn test_top_k_heavy_hitters() {
+        let top_k = TopK::default();
+        top_k.set_k(3);
+        for user in 0..1_000 {
+            top_k.observe(&format!("user-{user}"));
+        }
+        top_k.observe_n("user-hot", 500);
+        top_k.observe_n("user-warm", 200);
+        top_k.observe_n("user-tepid", 100);
+        let (top, tag_key) = top_k.reset();
+        assert_eq!(tag_key, DEFAULT_TOP_K_TAG);
+        let keys: Vec<_> = top.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["user-hot", "user-warm", "user-tepid"]);
+        assert!(top[0].1 >= 500);
+        // Reset starts a fresh window
+        assert!(top_k.top().is_empty());
+        assert_eq!(top_k.observe("user-hot"), 1);
+    }
+
+    #[test]
+    fn test_top_k_evicts_lightest() {
+        let top_k = TopK::default();
+        top_k.set_k(2);
+        top_k.observe_n("a", 5);
+        top_k.observe_n("b", 2);
+        top_k.observe_n("a", 5);
+        // Not heavier than the lightest candidate
+        top_k.observe_n("c", 2);
+        assert_eq!(top_k.top(), [("a".to_string(), 10), ("b".to_string(), 2)]);
+        top_k.observe_n("c", 1);
+        assert_eq!(top_k.top(), [("a".to_string(), 10), ("c".to_string(), 3)]);
+        let state = top_k.lock();
+        assert_eq!(state.by_estimate.len(), state.candidates.len());
+    }
+
+    #[test]
+    fn test_set() {
+        let make = || Instrument::Set(Set::default());
+        // Matching type should succeed
+        let downcasted = make().downcast::<MetricType::Set>();
+        assert!(downcasted.is_ok());
+        // Non-matching types should fail
+        let downcasted = make().downcast::<MetricType::Count>();
+        assert!(downcasted.is_err());
+    }
+
+    #[test]
+    fn test_set_unique_values() {
+        let set = Set::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let set = set.clone();
+                // Every thread observes the same users
+                std::thread::spawn(move || (0..2_000).for_each(|user| set.observe(&user)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let estimate = set.reset().estimate();
+        assert!(
+            (estimate - 2_000.0).abs() < 100.0,
+            "estimate was {estimate}"
+        );
+        // Reset starts a fresh window
+        assert_eq!(set.estimate(), 0.0);
+        set.observe("user-1");
+        assert_eq!(set.estimate().round(), 1.0);
+    }
+
+    #[test]
+    fn test_up_down_counter() {
+        let make = || Instrument::UpDownCounter(UpDownCounter::default());
+        // Matching type should succeed
+        let downcasted = make().downcast::<MetricType::UpDownCounter>();
+        assert!(downcasted.is_ok());
+        // Non-matching types should fail
+        let downcasted = make().downcast::<MetricType::Count>();
+        assert!(downcasted.is_err());
+        let downcasted = make().downcast::<MetricType::Gauge>();
+        assert!(downcasted.is_err());
+    }
+
+    #[test]
+    fn test_up_down_counts() {
+        let in_flight = UpDownCounter::default();
+        assert_eq!(in_flight.increment(), 0);
+        assert_eq!(in_flight.add(5), 1);
+        assert_eq!(in_flight.sub(2), 6);
+        assert_eq!(in_flight.decrement(), 4);
+        assert_eq!(in_flight.sub(10), 3);
+        assert_eq!(in_flight.load(), -7);
+    }
+
+    #[test]
+    fn test_gauge_aggregation() {
+        let record_window = |gauge: &Gauge| {
+            let handles: Vec<_> = [3.0, -1.0, 10.0, 4.0]
+                .into_iter()
+                .map(|value| {
+                    let gauge = gauge.clone();
+                    std::thread::spawn(move || gauge.record(value))
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        };
+        for (aggregation, expected) in [
+            (GaugeAggregation::Max, 10.0),
+            (GaugeAggregation::Min, -1.0),
+            (GaugeAggregation::Sum, 16.0),
+            (GaugeAggregation::Mean, 4.0),
+        ] {
+            let gauge = Gauge::new(aggregation);
+            record_window(&gauge);
+            assert_eq!(gauge.load(), expected, "{aggregation:?}");
+            assert_eq!(gauge.reset(), Some(expected), "{aggregation:?}");
+        }
+        // Empty windows
+        assert_eq!(Gauge::new(GaugeAggregation::Max).reset(), None);
+        assert_eq!(Gauge::new(GaugeAggregation::Mean).reset(), None);
+        assert_eq!(Gauge::new(GaugeAggregation::Sum).reset(), Some(0.0));
+        // Last carries its value over into the next window
+        let gauge = Gauge::new(GaugeAggregation::Last);
+        gauge.record(2.0);
+        assert_eq!(gauge.reset(), Some(2.0));
+        assert_eq!(gauge.reset(), Some(2.0));
+    }
+
+    #[test]
+    fn test_gauge_swap_restarts_window() {
+        for (aggregation, previous) in [
+            (GaugeAggregation::Max, 5.0),
+            (GaugeAggregation::Min, 3.0),
+            (GaugeAggregation::Sum, 8.0),
+            (GaugeAggregation::Mean, 4.0),
+        ] {
+            let gauge = Gauge::new(aggregation);
+            gauge.record(3.0);
+            gauge.record(5.0);
+            assert_eq!(gauge.swap(1.0), previous, "{aggregation:?}");
+            assert_eq!(gauge.load(), 1.0, "{aggregation:?}");
+            assert_eq!(gauge.reset(), Some(1.0), "{aggregation:?}");
+        }
+        let gauge = Gauge::new(GaugeAggregation::Mean);
+        gauge.record(2.0);
+        gauge.record(4.0);
+        assert_eq!(gauge.swap(10.0), 3.0);
+        gauge.record(20.0);
+        assert_eq!(gauge.reset(), Some(15.0));
+    }
 
     #[test]
     fn test_measure_fn() {
@@ -306,11 +1464,38 @@ mod test {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn test_timer_guard() {
+        let timing_count = TimingCount::default().with_unit(UnitOfTime::Millis);
+        let fallible = |fail: bool| -> Result<(), ()> {
+            let _timer = timing_count.start_timer();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            if fail {
+                Err(())?;
+            }
+            Ok(())
+        };
+        // Both the early exit and the regular exit are recorded
+        assert!(fallible(true).is_err());
+        assert!(fallible(false).is_ok());
+        let (sum, count) = timing_count.reset();
+        assert!(sum >= 40);
+        assert_eq!(count, 2);
+
+        let duration = timing_count.start_timer().observe_duration();
+        let (sum, count) = timing_count.reset();
+        assert_eq!(sum as u128, duration.as_millis());
+        assert_eq!(count, 1);
+
+        timing_count.start_timer().discard();
+        assert_eq!(timing_count.reset(), (0, 0));
+    }
+
     #[test]
     fn test_measure_fn_micros() {
         let time = 100;
         let timing_count = TimingCount::default().with_unit(UnitOfTime::Micros);
-        let _result = timing_count.measure_sync_fn(|| {
+        timing_count.measure_sync_fn(|| {
             std::thread::sleep(std::time::Duration::from_micros(time));
         });
         let (sum, count) = timing_count.reset();
@@ -57,6 +57,38 @@
 //!
 //! Gnort will automatically suffix the "sum of durations" as your stat name plus `".time"`. The count will be the stat name verbatim. So if your stat name is `"gnort.test.bench.timing_count"`, then you divide `"gnort.test.bench.timing_count.time"` by `"gnort.test.bench.timing_count"` to get the average time spent.
 //!
+//! Besides `measure_sync_fn` and `measure_async_fut`, `TimingCount::start_timer` returns a guard that records the elapsed time when
+//! it's dropped, so you can time arbitrary scopes including ones that exit early via `?`. Use `observe_duration()` to record explicitly
+//! or `discard()` to cancel.
+//!
+//! To tell successful from failed calls, register an [OutcomeTimingCount](outcome::OutcomeTimingCount) and time `Result`-returning
+//! closures or futures with `measure_result`/`measure_result_async`. They record into two `TimingCount`s tagged `outcome:success` and
+//! `outcome:failure`, optionally splitting failures further with an `error_kind` tag from `with_error_kind`. Failure series are
+//! registered the first time they record, so series for outcomes that never happen aren't emitted.
+//!
+//! When you need percentiles rather than averages, use `Histogram`. Values are recorded lock-free into log-linear buckets during the
+//! observation window and emitted as gauges suffixed with `.min`, `.max`, `.avg`, `.count`, `.p50`, `.p95` and `.p99`.
+//!
+//! `Distribution` is backed by a mergeable [DDSketch](sketch::DDSketch) with bounded relative error. It emits the same summary gauges
+//! by default, or can be switched to [DistributionEmission::Distribution](instrument::DistributionEmission) so that the pre-aggregated
+//! values are sent as DogStatsD distributions and percentiles can be computed across hosts. Each sketch bin is sent once with a
+//! `1/count` sample rate rather than once per observation.
+//!
+//! `TopK` tracks heavy hitters among high-cardinality keys like user IDs or routes with `observe(key)`. Frequencies are estimated in a
+//! fixed-memory [CountMinSketch](sketch::CountMinSketch) and each window only the K most frequent keys are emitted as counts tagged with
+//! `key:<key>`, so you can see hot keys without exploding tag cardinality. The counts are estimates that can overcount, by at most
+//! ~0.13% of the window's total observations with ~99% probability.
+//!
+//! `Set` counts unique values such as user IDs per window with a lock-free [HyperLogLog](sketch::HyperLogLog) and emits the cardinality
+//! estimate as a gauge, so the IDs themselves never go over the wire.
+//!
+//! By default a `Gauge` reports the last value written. Gauges registered with
+//! [register_gauge_with_aggregation](registry::MetricsRegistry::register_gauge_with_aggregation) instead combine every value passed to
+//! `record` within a window as its max, min, sum or mean, so concurrent writers don't overwrite each other's spikes.
+//!
+//! `UpDownCounter` is a signed counter for things like in-flight requests or queue depth. It is never reset and its running total is
+//! emitted as a gauge every window.
+//!
 //! ### Instantiating the MetricsRegistry for your metrics and registering them
 //!
 //! ```
@@ -79,6 +111,38 @@
 //!
 //! The metrics struct returned by the `register` static method is a struct-of-instruments that can be used to record observations with a well-typed interface that doesn't require juggling a bunch of individual metric references. You can then put one or more of these structs wherever you put the rest of your application's shared state, such as where you share your database connection pool.
 //!
+//! ## Instrument families
+//!
+//! For tags only known at runtime, like a route or status code, register a family once with its tag keys,
+//! `registry.register_count_family("http.requests", ["route", "status"])`, and record into
+//! `requests.with_values(&["/users", "200"])?`. Children are registered on first use and cached, see [family::Family].
+//!
+//! ## Limiting cardinality
+//!
+//! A tag with unbounded values, like a request ID, would otherwise grow the registry forever. Set
+//! `RegistryConfig::with_max_series_per_metric` and `with_max_series` to cap the number of series, tag combinations
+//! registered past a limit share their stat name's `overflow:true` series and are counted by the
+//! `gnort.registry.rejected_series` self-metric tagged with `metric:<name>`.
+//!
+//! ## Expiring idle series
+//!
+//! Every registered series is emitted every window until it's removed. `registry.unregister(metric)` removes one
+//! explicitly and `RegistryConfig::with_idle_expiry(windows)` removes series that weren't updated for that many
+//! windows. Instrument handles stay usable either way, updating one or registering the metric again puts the same
+//! instrument back into the registry with its pending window.
+//!
+//! ## Skipping zero and unchanged series
+//!
+//! By default every series is emitted every window, even counts of zero and gauges nobody wrote to. Pass an
+//! [EmissionPolicy] to `RegistryConfig::with_emission_policy`, or to `registry.set_emission_policy(metric, policy)` for a
+//! single series, to skip zero counts and unchanged gauges, with a heartbeat every N windows so the series don't vanish.
+//!
+//! ## Scoped registries
+//!
+//! Components can share one registry while keeping their metrics apart: `registry.scoped("payments.", ["component:payments"])`
+//! returns a [MetricsRegistry] that prefixes every name and adds the tags to every metric registered through it,
+//! including metrics structs, families and outcome timers. Scopes can be scoped again, e.g. `payments.scoped("stripe.", ["provider:stripe"])`.
+//!
 //! ## Ad-hoc metrics (high-level API)
 //!
 //! In cases where you need ad-hoc metrics (highly variable tagging perhaps?), you aren't limited to dropping down to the raw client. There's an `adhoc_metrics_struct` macro as well.
@@ -98,6 +162,69 @@
 //!     .adhoc_count(client, self.columns as i64, btreeset!{});
 //! ```
 //!
+//! ## Custom sinks
+//!
+//! [MetricsRegistry] emits through the [Sink](sink::Sink) trait, which [GnortClient] implements. To send aggregated metrics
+//! somewhere other than DogStatsD, or to a test double, implement `Sink` and pass it with `RegistryConfig::default().with_sink(Arc::new(my_sink))`.
+//!
+//! ## Shutting down
+//!
+//! The registry's emitter thread runs until you stop it. Call `registry.flush_and_stop()` (or `shutdown(timeout)`) before your process
+//! exits to emit whatever accumulated in the last window, or hold on to `registry.shutdown_guard(timeout)` to do so when it's dropped.
+//!
+//! ## Aligned observation windows
+//!
+//! By default windows are timed from when the registry was created. With `alignment: WindowAlignment::epoch()` in [RegistryConfig]
+//! they start on multiples of the observation period since the UNIX epoch instead, matching the agent's flush interval. Use
+//! `WindowAlignment::epoch_with_host_jitter(max)` to offset each host by a stable amount so a fleet doesn't emit all at once.
+//!
+//! ## Driving emission yourself
+//!
+//! Set `emission: EmissionMode::Manual` in [RegistryConfig] to skip the emitter thread. Call `registry.flush()` to run an emission
+//! cycle or poll `registry.flush_if_due()` from your own scheduler. Pair it with a [FakeClock](clock::FakeClock) via
+//! `RegistryConfig::with_clock` to test time-dependent behavior without sleeping.
+//!
+//! ## Emitting from a tokio runtime
+//!
+//! With the `tokio` feature enabled, `registry.spawn_on(&tokio::runtime::Handle::current())` replaces the registry's emitter thread
+//! with a task on your runtime. Use `registry.shutdown_async(timeout).await` to flush the last window before the runtime shuts down.
+//!
+//! ## Prometheus
+//!
+//! With the `prometheus` feature enabled, register your metrics with a registry emitting into a
+//! [PrometheusExporter](prometheus::PrometheusExporter) via `RegistryConfig::default().with_sink(exporter.clone())`.
+//! `exporter.render()` returns the text exposition format, with counts as cumulative counters, `TimingCount`s as
+//! `_sum`/`_count` summaries and tags split on `:` into labels, and `exporter.serve("0.0.0.0:9090")` serves it on `/metrics`.
+//! To keep sending to DogStatsD as well, pass both to a [FanoutSink](sink::FanoutSink), e.g.
+//! `FanoutSink::new([Arc::new(client) as Arc<dyn Sink>, exporter.clone()])`.
+//!
+//! ## OpenTelemetry
+//!
+//! With the `otlp` feature enabled, emit into an [OtlpSink](otlp::OtlpSink) to push every emission cycle to an
+//! OpenTelemetry collector's OTLP/HTTP endpoint as JSON, e.g.
+//! `OtlpSink::new(OtlpConfig::default().with_endpoint("http://localhost:4318/v1/metrics").with_service_name("api"))`.
+//! Counts become delta sums, gauges stay gauges and timings and distributions become delta histograms with explicit buckets.
+//! `TimingCount`s become delta histograms with only a sum and count.
+//!
+//! ## Testing your metrics
+//!
+//! With the `testing` feature enabled, `gnort::testing::recording_registry()` returns a registry emitting into an in-memory
+//! [RecordingSink](testing::RecordingSink). Call `registry.flush()` to run an emission cycle, then assert on what was emitted
+//! with `sink.assert_count("name", &["tag:x"], 3)`, `sink.assert_gauge(..)` or inspect every point with `sink.snapshot()`.
+//!
+//! To check what actually goes over the wire, bind a [MockAgent](testing::MockAgent) with `MockAgent::bind_udp()` or
+//! `MockAgent::bind_unix_datagram()` and emit through `agent.client()`. It decodes every datagram it receives with
+//! [protocol::parse_packet] so you can assert on `agent.metrics()`, `agent.messages()` or the raw `agent.packets()`.
+//!
+//! ## Unix domain sockets
+//!
+//! [GnortClient::new] sends over UDP to `STATSD_HOST`:`STATSD_PORT` unless `DD_DOGSTATSD_URL` is set, e.g. to
+//! `unix:///var/run/datadog/dsd.socket` for the agent's datagram socket or `unixstream:///var/run/datadog/dsd.socket`
+//! for its stream socket. Use [GnortClient::with_endpoint] to pick a [transport::Endpoint] in code instead.
+//!
+//! Metrics emitted by the registry are batched, newline separated, into packets of up to 1432 bytes over UDP and 8192 bytes
+//! over Unix domain sockets, see [GnortClient::with_max_packet_size].
+//!
 //! ## Synchronous-only client
 //!
 //! The client is sync-only because you should be aggregating your metrical emissions and emitting them in a background thread once every ~10-30 seconds depending on your needs.
@@ -111,13 +238,42 @@
 /// You usually don't need to poke around this module, you just instantiate clients
 /// for use with [MetricsRegistry](registry::MetricsRegistry).
 pub mod client;
+/// [Clock](clock::Clock) abstraction driving emission schedules and rate limiting, swap in a [FakeClock](clock::FakeClock) in tests.
+pub mod clock;
+/// Background thread or tokio task driving a registry's emission cycles.
+mod emitter;
+/// [Family](family::Family) looks up instruments by tag values, e.g. per route and status code, without re-registering on the hot path.
+pub mod family;
 /// [Instrument](instrument::Instrument) is the core type for metrical values. It is the value type used to register metrics with [MetricsRegistry](registry::MetricsRegistry).
 pub mod instrument;
 pub mod macros;
 /// [Metric] is the core type for metrical metadata. It is the key type used to register metrics with [MetricsRegistry](registry::MetricsRegistry).
 pub mod metric;
+/// [OtlpSink](otlp::OtlpSink) exports each emission cycle to an OpenTelemetry collector over OTLP/HTTP.
+/// Requires the `otlp` feature.
+#[cfg(any(test, feature = "otlp"))]
+pub mod otlp;
+/// [OutcomeTimingCount](outcome::OutcomeTimingCount) times fallible work into per-outcome [TimingCount](instrument::TimingCount) series.
+pub mod outcome;
+/// [PrometheusExporter](prometheus::PrometheusExporter) renders registry contents in the Prometheus text format.
+/// Requires the `prometheus` feature.
+#[cfg(any(test, feature = "prometheus"))]
+pub mod prometheus;
+/// [Encoder](protocol::Encoder) writes the DogStatsD wire format [GnortClient] sends, [parse_packet](protocol::parse_packet) reads it back.
+pub mod protocol;
 /// [MetricsRegistry] is how metrics are registered and emitted.
 pub mod registry;
+/// [Sink](sink::Sink) decouples [MetricsRegistry] from DogStatsD so aggregated metrics can go to any backend.
+pub mod sink;
+/// Mergeable sketches backing [Distribution](instrument::Distribution), [TopK](instrument::TopK) and [Set](instrument::Set).
+pub mod sketch;
+/// [RecordingSink](testing::RecordingSink) captures emissions in memory for unit testing your metrics,
+/// [MockAgent](testing::MockAgent) receives them over a real socket.
+/// Requires the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+/// [Endpoint](transport::Endpoint) and [Transport](transport::Transport) select how [GnortClient] reaches the agent: UDP or Unix domain sockets.
+pub mod transport;
 
 pub use client::GnortClient;
 pub use metric::*;
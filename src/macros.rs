@@ -12,6 +12,26 @@ macro_rules! metric {
         pub const $binding: $crate::metric::MetricName<MetricType::TimingCount> =
             $crate::metric::MetricName::timing_count($metric_name);
     };
+    ( $binding:ident, $metric_name:literal, Histogram ) => {
+        pub const $binding: $crate::metric::MetricName<MetricType::Histogram> =
+            $crate::metric::MetricName::histogram($metric_name);
+    };
+    ( $binding:ident, $metric_name:literal, Distribution ) => {
+        pub const $binding: $crate::metric::MetricName<MetricType::Distribution> =
+            $crate::metric::MetricName::distribution($metric_name);
+    };
+    ( $binding:ident, $metric_name:literal, TopK ) => {
+        pub const $binding: $crate::metric::MetricName<MetricType::TopK> =
+            $crate::metric::MetricName::top_k($metric_name);
+    };
+    ( $binding:ident, $metric_name:literal, Set ) => {
+        pub const $binding: $crate::metric::MetricName<MetricType::Set> =
+            $crate::metric::MetricName::set($metric_name);
+    };
+    ( $binding:ident, $metric_name:literal, UpDownCounter ) => {
+        pub const $binding: $crate::metric::MetricName<MetricType::UpDownCounter> =
+            $crate::metric::MetricName::up_down_counter($metric_name);
+    };
 }
 
 // TODO: metrics_module has a similar but not identical thing for this that is Metric instead of MetricName
@@ -130,7 +150,7 @@ macro_rules! metrics_module {
             pub struct Instruments {
                 $(
                     // TODO: Generate module with two struct types, one of the metrics/metric names, one of the instruments?
-                    pub $field_name: crate::instrument::$metric_type,
+                    pub $field_name: $crate::instrument::$metric_type,
                 )+
             }
 
@@ -1,10 +1,17 @@
-use std::{collections::BTreeSet, marker::PhantomData};
+use std::{
+    collections::{BTreeSet, HashSet},
+    marker::PhantomData,
+    sync::{Mutex, PoisonError},
+};
 
-use dogstatsd::DogstatsdResult;
 use maplit::btreeset;
+use once_cell::sync::Lazy;
 
 use crate::{
-    instrument::{Count, Gauge, Instrument, TimingCount},
+    client::DogstatsdResult,
+    instrument::{
+        Count, Distribution, Gauge, Histogram, Instrument, Set, TimingCount, TopK, UpDownCounter,
+    },
     GnortClient,
 };
 
@@ -41,6 +48,51 @@ pub mod MetricType {
             "timing_count".to_string()
         }
     }
+
+    /// Histogram
+    #[derive(Copy, Clone)]
+    pub enum Histogram {}
+    impl Impl for Histogram {
+        fn name() -> String {
+            "histogram".to_string()
+        }
+    }
+
+    /// Distribution
+    #[derive(Copy, Clone)]
+    pub enum Distribution {}
+    impl Impl for Distribution {
+        fn name() -> String {
+            "distribution".to_string()
+        }
+    }
+
+    /// TopK
+    #[derive(Copy, Clone)]
+    pub enum TopK {}
+    impl Impl for TopK {
+        fn name() -> String {
+            "top_k".to_string()
+        }
+    }
+
+    /// Set
+    #[derive(Copy, Clone)]
+    pub enum Set {}
+    impl Impl for Set {
+        fn name() -> String {
+            "set".to_string()
+        }
+    }
+
+    /// UpDownCounter
+    #[derive(Copy, Clone)]
+    pub enum UpDownCounter {}
+    impl Impl for UpDownCounter {
+        fn name() -> String {
+            "up_down_counter".to_string()
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -73,6 +125,31 @@ impl<'a> MetricName<'a, MetricType::TimingCount> {
     //     Self::timing_count(name)
     // }
 }
+impl<'a> MetricName<'a, MetricType::Histogram> {
+    pub const fn histogram(name: &'a str) -> Self {
+        Self(name, PhantomData)
+    }
+}
+impl<'a> MetricName<'a, MetricType::Distribution> {
+    pub const fn distribution(name: &'a str) -> Self {
+        Self(name, PhantomData)
+    }
+}
+impl<'a> MetricName<'a, MetricType::TopK> {
+    pub const fn top_k(name: &'a str) -> Self {
+        Self(name, PhantomData)
+    }
+}
+impl<'a> MetricName<'a, MetricType::Set> {
+    pub const fn set(name: &'a str) -> Self {
+        Self(name, PhantomData)
+    }
+}
+impl<'a> MetricName<'a, MetricType::UpDownCounter> {
+    pub const fn up_down_counter(name: &'a str) -> Self {
+        Self(name, PhantomData)
+    }
+}
 
 impl From<MetricName<'static, MetricType::Count>> for Metric<MetricType::Count> {
     fn from(m: MetricName<'static, MetricType::Count>) -> Metric<MetricType::Count> {
@@ -98,7 +175,38 @@ impl From<MetricName<'static, MetricType::TimingCount>> for Metric<MetricType::T
     }
 }
 
-// TODO: Histogram/Distribution is needed before we can do non-count timings
+impl From<MetricName<'static, MetricType::Histogram>> for Metric<MetricType::Histogram> {
+    fn from(m: MetricName<'static, MetricType::Histogram>) -> Metric<MetricType::Histogram> {
+        Metric::new_histogram(m)
+    }
+}
+
+impl From<MetricName<'static, MetricType::Distribution>> for Metric<MetricType::Distribution> {
+    fn from(m: MetricName<'static, MetricType::Distribution>) -> Metric<MetricType::Distribution> {
+        Metric::new_distribution(m)
+    }
+}
+
+impl From<MetricName<'static, MetricType::TopK>> for Metric<MetricType::TopK> {
+    fn from(m: MetricName<'static, MetricType::TopK>) -> Metric<MetricType::TopK> {
+        Metric::new_top_k(m)
+    }
+}
+
+impl From<MetricName<'static, MetricType::Set>> for Metric<MetricType::Set> {
+    fn from(m: MetricName<'static, MetricType::Set>) -> Metric<MetricType::Set> {
+        Metric::new_set(m)
+    }
+}
+
+impl From<MetricName<'static, MetricType::UpDownCounter>> for Metric<MetricType::UpDownCounter> {
+    fn from(
+        m: MetricName<'static, MetricType::UpDownCounter>,
+    ) -> Metric<MetricType::UpDownCounter> {
+        Metric::new_up_down_counter(m)
+    }
+}
+
 #[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
 pub struct Metric<T: MetricType::Impl> {
     /// Name of the metric, called stat in dogstatsd
@@ -118,19 +226,54 @@ impl From<&'static str> for Metric<MetricType::Count> {
 }
 
 impl From<&'static str> for Metric<MetricType::Gauge> {
-    // Default metrics derived from bare names to counts
+    // Default metrics derived from bare names to gauges
     fn from(metric_name: &'static str) -> Metric<MetricType::Gauge> {
         Metric::new_gauge(MetricName::gauge(metric_name))
     }
 }
 
 impl From<&'static str> for Metric<MetricType::TimingCount> {
-    // Default metrics derived from bare names to counts
+    // Default metrics derived from bare names to timing counts
     fn from(metric_name: &'static str) -> Metric<MetricType::TimingCount> {
         Metric::new_timing_count(MetricName::timing_count(metric_name))
     }
 }
 
+impl From<&'static str> for Metric<MetricType::Histogram> {
+    // Default metrics derived from bare names to histograms
+    fn from(metric_name: &'static str) -> Metric<MetricType::Histogram> {
+        Metric::new_histogram(MetricName::histogram(metric_name))
+    }
+}
+
+impl From<&'static str> for Metric<MetricType::Distribution> {
+    // Default metrics derived from bare names to distributions
+    fn from(metric_name: &'static str) -> Metric<MetricType::Distribution> {
+        Metric::new_distribution(MetricName::distribution(metric_name))
+    }
+}
+
+impl From<&'static str> for Metric<MetricType::TopK> {
+    // Default metrics derived from bare names to top-k heavy hitters
+    fn from(metric_name: &'static str) -> Metric<MetricType::TopK> {
+        Metric::new_top_k(MetricName::top_k(metric_name))
+    }
+}
+
+impl From<&'static str> for Metric<MetricType::Set> {
+    // Default metrics derived from bare names to sets
+    fn from(metric_name: &'static str) -> Metric<MetricType::Set> {
+        Metric::new_set(MetricName::set(metric_name))
+    }
+}
+
+impl From<&'static str> for Metric<MetricType::UpDownCounter> {
+    // Default metrics derived from bare names to up/down counters
+    fn from(metric_name: &'static str) -> Metric<MetricType::UpDownCounter> {
+        Metric::new_up_down_counter(MetricName::up_down_counter(metric_name))
+    }
+}
+
 impl Metric<MetricType::Count> {
     pub fn new_count(metric_name: MetricName<'static, MetricType::Count>) -> Self {
         Self {
@@ -191,6 +334,67 @@ impl Metric<MetricType::TimingCount> {
     }
 }
 
+impl Metric<MetricType::Histogram> {
+    pub fn new_histogram(metric_name: MetricName<'static, MetricType::Histogram>) -> Self {
+        Self {
+            metric_name: metric_name.into(),
+            metric_tags: btreeset![],
+            metric_type: PhantomData,
+        }
+    }
+}
+
+impl Metric<MetricType::Distribution> {
+    pub fn new_distribution(metric_name: MetricName<'static, MetricType::Distribution>) -> Self {
+        Self {
+            metric_name: metric_name.into(),
+            metric_tags: btreeset![],
+            metric_type: PhantomData,
+        }
+    }
+    pub fn adhoc_distribution(
+        &self,
+        client: &GnortClient,
+        value: f64,
+        adhoc_tags: BTreeSet<String>,
+    ) -> DogstatsdResult {
+        let emission_tags = self.metric_tags.union(&adhoc_tags);
+        client.distribution(self.metric_name, value.to_string(), emission_tags)
+    }
+}
+
+impl Metric<MetricType::TopK> {
+    pub fn new_top_k(metric_name: MetricName<'static, MetricType::TopK>) -> Self {
+        Self {
+            metric_name: metric_name.into(),
+            metric_tags: btreeset![],
+            metric_type: PhantomData,
+        }
+    }
+}
+
+impl Metric<MetricType::Set> {
+    pub fn new_set(metric_name: MetricName<'static, MetricType::Set>) -> Self {
+        Self {
+            metric_name: metric_name.into(),
+            metric_tags: btreeset![],
+            metric_type: PhantomData,
+        }
+    }
+}
+
+impl Metric<MetricType::UpDownCounter> {
+    pub fn new_up_down_counter(
+        metric_name: MetricName<'static, MetricType::UpDownCounter>,
+    ) -> Self {
+        Self {
+            metric_name: metric_name.into(),
+            metric_tags: btreeset![],
+            metric_type: PhantomData,
+        }
+    }
+}
+
 #[allow(dead_code)]
 impl<T: MetricType::Impl + MakeInstrument> Metric<T> {
     pub fn make_instrument(&self) -> T::InstrumentType {
@@ -221,7 +425,7 @@ impl<T: MetricType::Impl + MakeInstrument> Metric<T> {
         }
     }
     pub fn with_vec_tags(self, metric_tags: Vec<String>) -> Self {
-        let metric_tags: BTreeSet<String> = metric_tags.into_iter().map(|t| t).collect();
+        let metric_tags: BTreeSet<String> = metric_tags.into_iter().collect();
         Self {
             metric_tags,
             ..self
@@ -267,6 +471,58 @@ impl MakeInstrument for MetricType::TimingCount {
     }
 }
 
+impl MakeInstrument for MetricType::Histogram {
+    type InstrumentType = Histogram;
+    fn make_instrument() -> Self::InstrumentType {
+        Instrument::histogram()
+    }
+}
+
+impl MakeInstrument for MetricType::Distribution {
+    type InstrumentType = Distribution;
+    fn make_instrument() -> Self::InstrumentType {
+        Instrument::distribution()
+    }
+}
+
+impl MakeInstrument for MetricType::TopK {
+    type InstrumentType = TopK;
+    fn make_instrument() -> Self::InstrumentType {
+        Instrument::top_k()
+    }
+}
+
+impl MakeInstrument for MetricType::Set {
+    type InstrumentType = Set;
+    fn make_instrument() -> Self::InstrumentType {
+        Instrument::set()
+    }
+}
+
+impl MakeInstrument for MetricType::UpDownCounter {
+    type InstrumentType = UpDownCounter;
+    fn make_instrument() -> Self::InstrumentType {
+        Instrument::up_down_counter()
+    }
+}
+
+/// Names built at runtime, leaked once each so they can be used like `&'static str` names.
+static INTERNED_NAMES: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(Default::default);
+
+pub(crate) fn intern_name(name: String) -> &'static str {
+    let mut names = INTERNED_NAMES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    match names.get(name.as_str()) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
 /// Type-erased Metric type for the metric map
 #[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
 pub(crate) struct MetricKey {
@@ -312,6 +568,16 @@ mod test {
         "gnort.test.bench.timing_count",
         TimingCount
     );
+    metric!(
+        TEST_HISTOGRAM_METRIC,
+        "gnort.test.bench.histogram",
+        Histogram
+    );
+    metric!(
+        TEST_DISTRIBUTION_METRIC,
+        "gnort.test.bench.distribution",
+        Distribution
+    );
 
     metrics_struct![
         TaglessMetrics,
@@ -348,6 +614,17 @@ mod test {
             test_timing_count,
             "gnort.test.bench.timing_count",
             TimingCount
+        ),
+        (
+            test_histogram,
+            "gnort.test.bench.histogram",
+            Histogram,
+            ["outcome:success"]
+        ),
+        (
+            test_distribution,
+            "gnort.test.bench.distribution",
+            Distribution
         )
     ];
 
@@ -361,6 +638,10 @@ mod test {
             TEST_TIMING_COUNT_METRIC.get_name(),
             "gnort.test.bench.timing_count"
         );
+        assert_eq!(
+            TEST_HISTOGRAM_METRIC.get_name(),
+            "gnort.test.bench.histogram"
+        );
         assert_eq!(test_metrics.test_count.increment(), 0);
         assert_eq!(test_metrics.test_count.increment(), 1);
         assert_eq!(test_metrics.test_gauge.swap(5.5), 0.0);
@@ -378,6 +659,17 @@ mod test {
                 .add_timing(&std::time::Duration::from_secs(10)),
             (5_000, 1)
         );
+        test_metrics.test_histogram.record(5.0);
+        test_metrics.test_histogram.record(10.0);
+        let summary = test_metrics.test_histogram.reset();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.avg(), 7.5);
+        assert_eq!(
+            TEST_DISTRIBUTION_METRIC.get_name(),
+            "gnort.test.bench.distribution"
+        );
+        test_metrics.test_distribution.record(5.0);
+        assert_eq!(test_metrics.test_distribution.quantile(1.0), Some(5.0));
     }
 
     #[test]
@@ -413,7 +705,9 @@ mod test {
             test_timing_count,
             "gnort.test.bench.timing_count",
             TimingCount
-        )
+        ),
+        (test_set, "gnort.test.bench.unique_users", Set),
+        (test_in_flight, "gnort.test.bench.in_flight", UpDownCounter)
     ];
 
     #[test]
@@ -421,6 +715,11 @@ mod test {
         let registry = MetricsRegistry::new(RegistryConfig::default());
         let test_instruments =
             test_module::Instruments::register(&registry).expect("Failed to register metrics!");
+        test_instruments.test_set.observe("user-1");
+        test_instruments.test_set.observe("user-1");
+        assert_eq!(test_instruments.test_set.estimate().round(), 1.0);
+        assert_eq!(test_instruments.test_in_flight.increment(), 0);
+        assert_eq!(test_instruments.test_in_flight.decrement(), 1);
         assert_eq!(test_instruments.test_count.increment(), 0);
         assert_eq!(test_instruments.test_gauge.swap(5.5), 0.0);
         assert_eq!(
@@ -0,0 +1,607 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::sink::{Sink, SinkError, SinkResult};
+
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318/v1/metrics";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const SCOPE_NAME: &str = "gnort";
+// AGGREGATION_TEMPORALITY_DELTA in the OTLP metrics proto
+const DELTA: u8 = 1;
+
+/// Where and how [OtlpSink] exports.
+#[derive(Clone, Debug)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP metrics endpoint of a collector, only plain `http://` is supported.
+    /// Defaults to [DEFAULT_OTLP_ENDPOINT].
+    pub endpoint: String,
+    /// Sent as the `service.name` resource attribute
+    pub service_name: Option<String>,
+    /// Extra resource attributes
+    pub resource_attributes: Vec<(String, String)>,
+    /// Connect, write and read timeout for each export
+    pub timeout: Duration,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: DEFAULT_OTLP_ENDPOINT.to_string(),
+            service_name: None,
+            resource_attributes: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl OtlpConfig {
+    pub fn with_endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+    pub fn with_service_name<S: Into<String>>(mut self, service_name: S) -> Self {
+        self.service_name = Some(service_name.into());
+        self
+    }
+    pub fn with_resource_attribute<K: Into<String>, V: Into<String>>(
+        mut self,
+        key: K,
+        value: V,
+    ) -> Self {
+        self.resource_attributes.push((key.into(), value.into()));
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Point {
+    /// Delta sum of a count over the window
+    Sum(i64),
+    Gauge(f64),
+    /// Delta histogram, `buckets` are distinct values and their counts sorted by value
+    Histogram {
+        count: u64,
+        sum: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+        buckets: Vec<(f64, u64)>,
+    },
+}
+
+impl Point {
+    fn histogram(values: &[f64]) -> Self {
+        let bins: Vec<(f64, u64)> = values.iter().map(|value| (*value, 1)).collect();
+        Self::weighted_histogram(&bins)
+    }
+    /// Non-finite values are dropped, JSON can't carry them as bucket bounds.
+    fn weighted_histogram(bins: &[(f64, u64)]) -> Self {
+        let mut buckets: Vec<(f64, u64)> = bins
+            .iter()
+            .copied()
+            .filter(|(value, count)| *count > 0 && value.is_finite())
+            .collect();
+        buckets.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        buckets.dedup_by(|(value, count), (previous, total)| {
+            let duplicate = value == previous;
+            if duplicate {
+                *total += *count;
+            }
+            duplicate
+        });
+        Point::Histogram {
+            count: buckets.iter().map(|(_, count)| count).sum(),
+            sum: buckets
+                .iter()
+                .map(|(value, count)| value * *count as f64)
+                .sum(),
+            min: buckets.first().map(|(value, _)| *value),
+            max: buckets.last().map(|(value, _)| *value),
+            buckets,
+        }
+    }
+}
+
+/// Data points of one emission cycle, keyed by metric name.
+type Batch = BTreeMap<String, Vec<(Vec<(String, String)>, Point)>>;
+
+#[derive(Debug)]
+struct OtlpState {
+    batch: Batch,
+    /// Start of the current window in nanoseconds since the UNIX epoch
+    window_start: u64,
+}
+
+/// [Sink] exporting each emission cycle of a [MetricsRegistry](crate::MetricsRegistry) to an
+/// OpenTelemetry collector as an OTLP/HTTP JSON `ExportMetricsServiceRequest`.
+///
+/// Counts are sent as monotonic delta sums, gauges as gauges, and timings and distributions as delta
+/// histograms with an explicit bucket bounded by each distinct value, or by each sketch bin for the
+/// registry's [Distribution](crate::instrument::Distribution)s. [TimingCount](crate::instrument::TimingCount)s
+/// only have a sum and count, so they're sent as histograms without buckets. Tags are split on the first `:`
+/// into string attributes. Data points are buffered until the registry calls [Sink::flush] at the end
+/// of the cycle, which sends them in a single request.
+#[derive(Debug)]
+pub struct OtlpSink {
+    config: OtlpConfig,
+    state: Mutex<OtlpState>,
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn attributes(tags: &[&str]) -> Vec<(String, String)> {
+    tags.iter()
+        .map(|tag| {
+            let (key, value) = tag.split_once(':').unwrap_or((tag, "true"));
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+fn push_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// JSON numbers can't be NaN or infinite
+fn push_json_f64(out: &mut String, value: f64) {
+    if value.is_finite() {
+        let _ = write!(out, "{value}");
+    } else {
+        out.push('0');
+    }
+}
+
+fn push_attributes<'a, I: IntoIterator<Item = &'a (String, String)>>(
+    out: &mut String,
+    attributes: I,
+) {
+    out.push_str("\"attributes\":[");
+    for (i, (key, value)) in attributes.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"key\":");
+        push_json_string(out, key);
+        out.push_str(",\"value\":{\"stringValue\":");
+        push_json_string(out, value);
+        out.push_str("}}");
+    }
+    out.push(']');
+}
+
+impl OtlpSink {
+    pub fn new(config: OtlpConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(OtlpState {
+                batch: Batch::new(),
+                window_start: now_nanos(),
+            }),
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, OtlpState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn push(&self, name: &str, tags: &[&str], point: Point) {
+        self.lock()
+            .batch
+            .entry(name.to_string())
+            .or_default()
+            .push((attributes(tags), point));
+    }
+    /// Renders `batch` as an OTLP JSON `ExportMetricsServiceRequest` for the window from
+    /// `start` to `end`, in nanoseconds since the UNIX epoch.
+    fn encode(&self, batch: &Batch, start: u64, end: u64) -> String {
+        let mut out = String::from("{\"resourceMetrics\":[{\"resource\":{");
+        let service_name = self
+            .config
+            .service_name
+            .iter()
+            .map(|name| ("service.name".to_string(), name.clone()));
+        let resource: Vec<(String, String)> = service_name
+            .chain(self.config.resource_attributes.iter().cloned())
+            .collect();
+        push_attributes(&mut out, &resource);
+        out.push_str("},\"scopeMetrics\":[{\"scope\":{\"name\":");
+        push_json_string(&mut out, SCOPE_NAME);
+        out.push_str(",\"version\":");
+        push_json_string(&mut out, env!("CARGO_PKG_VERSION"));
+        out.push_str("},\"metrics\":[");
+        let mut first_metric = true;
+        for (name, points) in batch {
+            // A name emitted as different kinds becomes one metric per kind
+            for kind in ["sum", "gauge", "histogram"] {
+                let points: Vec<_> = points
+                    .iter()
+                    .filter(|(_, point)| {
+                        kind == match point {
+                            Point::Sum(_) => "sum",
+                            Point::Gauge(_) => "gauge",
+                            Point::Histogram { .. } => "histogram",
+                        }
+                    })
+                    .collect();
+                if points.is_empty() {
+                    continue;
+                }
+                if !first_metric {
+                    out.push(',');
+                }
+                first_metric = false;
+                out.push_str("{\"name\":");
+                push_json_string(&mut out, name);
+                let _ = write!(out, ",\"{kind}\":{{\"dataPoints\":[");
+                for (i, (attributes, point)) in points.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push('{');
+                    push_attributes(&mut out, attributes);
+                    let _ = write!(
+                        out,
+                        ",\"startTimeUnixNano\":\"{start}\",\"timeUnixNano\":\"{end}\""
+                    );
+                    match point {
+                        // 64 bit integers are strings in OTLP JSON
+                        Point::Sum(value) => {
+                            let _ = write!(out, ",\"asInt\":\"{value}\"");
+                        }
+                        Point::Gauge(value) => {
+                            out.push_str(",\"asDouble\":");
+                            push_json_f64(&mut out, *value);
+                        }
+                        Point::Histogram {
+                            count,
+                            sum,
+                            min,
+                            max,
+                            buckets,
+                        } => {
+                            let _ = write!(out, ",\"count\":\"{count}\",\"sum\":");
+                            push_json_f64(&mut out, *sum);
+                            for (field, value) in [("min", min), ("max", max)] {
+                                if let Some(value) = value {
+                                    let _ = write!(out, ",\"{field}\":");
+                                    push_json_f64(&mut out, *value);
+                                }
+                            }
+                            if !buckets.is_empty() {
+                                // Each value is the upper bound of its bucket, leaving the
+                                // overflow bucket past the last bound empty
+                                out.push_str(",\"bucketCounts\":[");
+                                for (_, count) in buckets.iter() {
+                                    let _ = write!(out, "\"{count}\",");
+                                }
+                                out.push_str("\"0\"],\"explicitBounds\":[");
+                                for (i, (value, _)) in buckets.iter().enumerate() {
+                                    if i > 0 {
+                                        out.push(',');
+                                    }
+                                    push_json_f64(&mut out, *value);
+                                }
+                                out.push(']');
+                            }
+                        }
+                    }
+                    out.push('}');
+                }
+                out.push(']');
+                match kind {
+                    "sum" => {
+                        let _ = write!(
+                            out,
+                            ",\"aggregationTemporality\":{DELTA},\"isMonotonic\":true"
+                        );
+                    }
+                    "histogram" => {
+                        let _ = write!(out, ",\"aggregationTemporality\":{DELTA}");
+                    }
+                    _ => {}
+                }
+                out.push_str("}}");
+            }
+        }
+        out.push_str("]}]}]}");
+        out
+    }
+    /// POSTs `body` to the configured endpoint, any non-2xx status is an error.
+    /// Tries every address `address` resolves to, each bounded by the configured timeout.
+    fn connect(&self, address: &str) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.config.timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{address} didn't resolve to any address"),
+            )
+        }))
+    }
+    fn post(&self, body: &str) -> SinkResult {
+        let url = self.config.endpoint.as_str();
+        let invalid_endpoint = || SinkError::Other(format!("Unsupported OTLP endpoint {url:?}"));
+        let rest = url.strip_prefix("http://").ok_or_else(invalid_endpoint)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(invalid_endpoint());
+        }
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        let mut stream = self.connect(&address)?;
+        stream.set_read_timeout(Some(self.config.timeout))?;
+        stream.set_write_timeout(Some(self.config.timeout))?;
+        write!(
+            stream,
+            "POST {path} HTTP/1.1\r\nHost: {authority}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()?;
+        let mut status_line = String::new();
+        BufReader::new(&stream).read_line(&mut status_line)?;
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(SinkError::Other(format!(
+                "OTLP export to {url} failed: {}",
+                status_line.trim_end()
+            ))),
+        }
+    }
+}
+
+impl Sink for OtlpSink {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult {
+        self.push(name, tags, Point::Sum(value));
+        Ok(())
+    }
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult {
+        self.push(name, tags, Point::Gauge(value));
+        Ok(())
+    }
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult {
+        self.push(name, tags, Point::histogram(&[milliseconds as f64]));
+        Ok(())
+    }
+    /// Set members have no OTLP equivalent and are dropped, the registry's
+    /// [Set](crate::instrument::Set) instrument emits its estimate as a gauge instead.
+    fn set(&self, _name: &str, _value: &str, _tags: &[&str]) -> SinkResult {
+        Ok(())
+    }
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult {
+        self.push(name, tags, Point::histogram(values));
+        Ok(())
+    }
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        self.push(name, tags, Point::weighted_histogram(bins));
+        Ok(())
+    }
+    fn timing_count(&self, name: &str, sum: i64, count: i64, tags: &[&str]) -> SinkResult {
+        let point = Point::Histogram {
+            count: count.max(0) as u64,
+            sum: sum as f64,
+            min: None,
+            max: None,
+            buckets: Vec::new(),
+        };
+        self.push(name, tags, point);
+        Ok(())
+    }
+    /// Sends everything emitted since the last flush as one request, nothing is sent for an empty cycle.
+    fn flush(&self) -> SinkResult {
+        let end = now_nanos();
+        let (batch, start) = {
+            let mut state = self.lock();
+            let start = std::mem::replace(&mut state.window_start, end);
+            (std::mem::take(&mut state.batch), start)
+        };
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.post(&self.encode(&batch, start, end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Read,
+        net::TcpListener,
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{EmissionMode, Metric, MetricsRegistry, RegistryConfig};
+
+    /// Stand-in collector answering one request with `status`, sends back the request's path and body.
+    fn collector(status: &'static str) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some(length) = header.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(&stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+            let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+            sender
+                .send((path, String::from_utf8(body).unwrap()))
+                .unwrap();
+        });
+        (endpoint, receiver)
+    }
+
+    fn metric<'a>(request: &'a Value, name: &str) -> &'a Value {
+        request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|metric| metric["name"] == name)
+            .unwrap_or_else(|| panic!("no metric {name} in {request}"))
+    }
+
+    #[test]
+    fn test_export_emission_cycle() {
+        let (endpoint, requests) = collector("200 OK");
+        let sink = Arc::new(OtlpSink::new(
+            OtlpConfig::default()
+                .with_endpoint(endpoint)
+                .with_service_name("checkout"),
+        ));
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_sink(sink.clone())
+                .with_emission(EmissionMode::Manual),
+        );
+        let count = registry
+            .register_count(Metric::from("gnort.requests").with_tags(["route:/users"]))
+            .unwrap();
+        let gauge = registry.register_gauge("gnort.queue.depth").unwrap();
+        let timing = registry.register_timing_count("gnort.latency").unwrap();
+        count.fetch_add(3);
+        gauge.swap(4.5);
+        timing.add_timing(&Duration::from_millis(30));
+        timing.add_timing(&Duration::from_millis(10));
+        Sink::distribution(sink.as_ref(), "gnort.payload", &[3.5, 1.5, 3.5], &[]).unwrap();
+        registry.flush();
+
+        let (path, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(path, "/v1/metrics");
+        let request: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            request["resourceMetrics"][0]["resource"]["attributes"][0],
+            serde_json::json!({"key": "service.name", "value": {"stringValue": "checkout"}})
+        );
+        let sum = &metric(&request, "gnort.requests")["sum"];
+        assert_eq!(sum["aggregationTemporality"], 1);
+        assert_eq!(sum["isMonotonic"], true);
+        let point = &sum["dataPoints"][0];
+        assert_eq!(point["asInt"], "3");
+        assert_eq!(
+            point["attributes"][0],
+            serde_json::json!({"key": "route", "value": {"stringValue": "/users"}})
+        );
+        let start: u64 = point["startTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let end: u64 = point["timeUnixNano"].as_str().unwrap().parse().unwrap();
+        assert!(start <= end);
+        assert_eq!(
+            metric(&request, "gnort.queue.depth")["gauge"]["dataPoints"][0]["asDouble"],
+            4.5
+        );
+        let histogram = &metric(&request, "gnort.latency")["histogram"]["dataPoints"][0];
+        assert_eq!(histogram["count"], "2");
+        assert_eq!(histogram["sum"], 40.0);
+        assert!(histogram.get("bucketCounts").is_none());
+        let histogram = &metric(&request, "gnort.payload")["histogram"]["dataPoints"][0];
+        assert_eq!(histogram["count"], "3");
+        assert_eq!(histogram["explicitBounds"], serde_json::json!([1.5, 3.5]));
+        assert_eq!(
+            histogram["bucketCounts"],
+            serde_json::json!(["1", "2", "0"])
+        );
+    }
+
+    #[test]
+    fn test_weighted_histogram() {
+        let point =
+            Point::weighted_histogram(&[(2.5, 3), (0.5, 1), (f64::NAN, 4), (2.5, 1), (9.0, 0)]);
+        assert_eq!(
+            point,
+            Point::Histogram {
+                count: 5,
+                sum: 10.5,
+                min: Some(0.5),
+                max: Some(2.5),
+                buckets: vec![(0.5, 1), (2.5, 4)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_export_errors() {
+        let (endpoint, requests) = collector("503 Service Unavailable");
+        let sink = OtlpSink::new(OtlpConfig::default().with_endpoint(endpoint));
+        // Nothing to send, the collector isn't contacted
+        sink.flush().unwrap();
+        Sink::count(&sink, "gnort.requests", 1, &[]).unwrap();
+        let err = sink.flush().unwrap_err();
+        assert!(err.to_string().contains("503"), "{err}");
+        requests.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let sink = OtlpSink::new(OtlpConfig::default().with_endpoint("https://collector"));
+        Sink::count(&sink, "gnort.requests", 1, &[]).unwrap();
+        assert!(matches!(sink.flush(), Err(SinkError::Other(_))));
+
+        // Accepts but never responds, waiting for the response is bounded by the timeout
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let (release, released) = mpsc::channel::<()>();
+        let stalled = std::thread::spawn(move || {
+            let _stream = listener.accept().unwrap();
+            let _ = released.recv();
+        });
+        let sink = OtlpSink::new(OtlpConfig {
+            timeout: Duration::from_millis(200),
+            ..OtlpConfig::default().with_endpoint(endpoint)
+        });
+        Sink::count(&sink, "gnort.requests", 1, &[]).unwrap();
+        let start = std::time::Instant::now();
+        assert!(matches!(sink.flush(), Err(SinkError::Io(_))));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        drop(release);
+        stalled.join().unwrap();
+    }
+}
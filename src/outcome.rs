@@ -0,0 +1,238 @@
+use std::{future::Future, sync::Arc};
+
+use dashmap::DashMap;
+use once_cell::sync::OnceCell;
+use tracing::debug;
+
+use crate::{
+    instrument::{TimingCount, UnitOfTime},
+    Metric, MetricRegistrationError, MetricType, MetricsRegistry,
+};
+
+pub const OUTCOME_SUCCESS_TAG: &str = "outcome:success";
+pub const OUTCOME_FAILURE_TAG: &str = "outcome:failure";
+pub const ERROR_KIND_TAG_KEY: &str = "error_kind";
+
+type ErrorKindClassifier<E> = Arc<dyn Fn(&E) -> String + Send + Sync>;
+
+/// A pair of [TimingCount]s registered under the same stat name with `outcome:success` and
+/// `outcome:failure` tags. Timing a `Result`-returning closure or future records into the
+/// series matching its outcome, so one call gives you per-outcome latency and error rates.
+///
+/// With [OutcomeTimingCount::with_error_kind] failures are further split by an `error_kind`
+/// tag derived from the error, those series are registered the first time each kind is seen.
+/// The untagged `outcome:failure` series is registered on the first failure it records, so with
+/// a classifier it's only there if a failure couldn't be registered under its kind.
+pub struct OutcomeTimingCount<E> {
+    metric: Metric<MetricType::TimingCount>,
+    registry: MetricsRegistry,
+    success: TimingCount,
+    failure: Arc<OnceCell<TimingCount>>,
+    unit: UnitOfTime,
+    error_kind: Option<ErrorKindClassifier<E>>,
+    error_kinds: Arc<DashMap<String, TimingCount>>,
+}
+
+// Manual impl, derive would needlessly require `E: Clone`
+impl<E> Clone for OutcomeTimingCount<E> {
+    fn clone(&self) -> Self {
+        Self {
+            metric: self.metric.clone(),
+            registry: self.registry.clone(),
+            success: self.success.clone(),
+            failure: self.failure.clone(),
+            unit: self.unit,
+            error_kind: self.error_kind.clone(),
+            error_kinds: self.error_kinds.clone(),
+        }
+    }
+}
+
+impl<E> OutcomeTimingCount<E> {
+    /// [register]() has get_or_insert semantics for the `outcome:success` series, the
+    /// `outcome:failure` series is registered lazily by [OutcomeTimingCount::failure].
+    pub fn register<M>(
+        registry: &MetricsRegistry,
+        metric: M,
+    ) -> Result<Self, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::TimingCount>>,
+    {
+        let metric: Metric<MetricType::TimingCount> = metric.into();
+        let success =
+            registry.register_timing_count(Self::tagged(&metric, &[OUTCOME_SUCCESS_TAG]))?;
+        Ok(Self {
+            metric,
+            registry: registry.clone(),
+            success,
+            failure: Arc::new(OnceCell::new()),
+            unit: UnitOfTime::default(),
+            error_kind: None,
+            error_kinds: Arc::new(DashMap::new()),
+        })
+    }
+    fn tagged(
+        metric: &Metric<MetricType::TimingCount>,
+        tags: &[&str],
+    ) -> Metric<MetricType::TimingCount> {
+        let mut metric_tags = metric.get_tags().clone();
+        metric_tags.extend(tags.iter().map(|tag| tag.to_string()));
+        metric.clone().with_set_tags(metric_tags)
+    }
+    pub fn with_unit(self, unit: UnitOfTime) -> Self {
+        Self {
+            success: self.success.with_unit(unit),
+            unit,
+            ..self
+        }
+    }
+    /// Tags failures with `error_kind:<kind>` using `classify` instead of recording them
+    /// into the untagged `outcome:failure` series.
+    pub fn with_error_kind<F>(self, classify: F) -> Self
+    where
+        F: Fn(&E) -> String + Send + Sync + 'static,
+    {
+        Self {
+            error_kind: Some(Arc::new(classify)),
+            ..self
+        }
+    }
+    pub fn success(&self) -> &TimingCount {
+        &self.success
+    }
+    /// The untagged `outcome:failure` series, registered on first use.
+    pub fn failure(&self) -> Result<&TimingCount, MetricRegistrationError> {
+        self.failure.get_or_try_init(|| {
+            let metric = Self::tagged(&self.metric, &[OUTCOME_FAILURE_TAG]);
+            let timing_count = self.registry.register_timing_count(metric)?;
+            Ok(timing_count.with_unit(self.unit))
+        })
+    }
+    fn failure_for(&self, error: &E) -> Result<TimingCount, MetricRegistrationError> {
+        let Some(classify) = &self.error_kind else {
+            return self.failure().cloned();
+        };
+        let kind = classify(error);
+        if let Some(timing_count) = self.error_kinds.get(&kind) {
+            return Ok(timing_count.clone());
+        }
+        let kind_tag = format!("{}:{}", ERROR_KIND_TAG_KEY, kind);
+        let metric = Self::tagged(&self.metric, &[OUTCOME_FAILURE_TAG, &kind_tag]);
+        match self.registry.register_timing_count(metric) {
+            Ok(timing_count) => {
+                let timing_count = timing_count.with_unit(self.unit);
+                self.error_kinds.insert(kind, timing_count.clone());
+                Ok(timing_count)
+            }
+            Err(_) => self.failure().cloned(),
+        }
+    }
+    /// Records `duration` into the series matching `result`'s outcome.
+    pub fn record<T>(&self, result: &Result<T, E>, duration: &std::time::Duration) {
+        match result {
+            Ok(_) => {
+                self.success.add_timing(duration);
+            }
+            Err(error) => {
+                let _ = self
+                    .failure_for(error)
+                    .map(|failure| failure.add_timing(duration))
+                    .map_err(|err| debug!("Failed to register failure timing, was: {err}"));
+            }
+        }
+    }
+    pub fn measure_result<T, F: FnOnce() -> Result<T, E>>(&self, f: F) -> Result<T, E> {
+        let (result, duration) = TimingCount::measure_sync_fn_(f);
+        self.record(&result, &duration);
+        result
+    }
+    pub async fn measure_result_async<T, F: Future<Output = Result<T, E>>>(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let (result, duration) = TimingCount::measure_async_fut_(f).await;
+        self.record(&result, &duration);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{testing::recording_registry, RegistryConfig};
+
+    #[derive(Debug)]
+    enum TestError {
+        Timeout,
+        NotFound,
+    }
+
+    fn fallible(error: Option<TestError>) -> Result<usize, TestError> {
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        match error {
+            Some(error) => Err(error),
+            None => Ok(5),
+        }
+    }
+
+    #[test]
+    fn test_measure_result() {
+        let registry = MetricsRegistry::new(RegistryConfig::default());
+        let timing = OutcomeTimingCount::register(&registry, "gnort.test.commit.timing_count")
+            .expect("Failed to register metrics!");
+        assert_eq!(timing.measure_result(|| fallible(None)).unwrap(), 5);
+        assert!(timing
+            .measure_result(|| fallible(Some(TestError::Timeout)))
+            .is_err());
+        assert!(timing
+            .measure_result(|| fallible(Some(TestError::NotFound)))
+            .is_err());
+        // The same series are shared through the registry
+        let success = registry
+            .register_timing_count(
+                Metric::from("gnort.test.commit.timing_count").with_tags([OUTCOME_SUCCESS_TAG]),
+            )
+            .unwrap();
+        assert_eq!(success.add_timing(&std::time::Duration::ZERO).1, 1);
+        assert_eq!(
+            timing
+                .failure()
+                .unwrap()
+                .add_timing(&std::time::Duration::ZERO)
+                .1,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_measure_result_async_with_error_kind() {
+        let (registry, sink) = recording_registry();
+        let timing = OutcomeTimingCount::register(&registry, "gnort.test.fetch.timing_count")
+            .expect("Failed to register metrics!")
+            .with_error_kind(|error: &TestError| format!("{error:?}").to_lowercase());
+        let _ = timing
+            .measure_result_async(async { fallible(Some(TestError::Timeout)) })
+            .await;
+        let _ = timing
+            .measure_result_async(async { fallible(Some(TestError::Timeout)) })
+            .await;
+        let _ = timing.measure_result_async(async { fallible(None) }).await;
+        registry.flush();
+        let name = "gnort.test.fetch.timing_count";
+        sink.assert_count(name, &[OUTCOME_FAILURE_TAG, "error_kind:timeout"], 2);
+        sink.assert_count(name, &[OUTCOME_SUCCESS_TAG], 1);
+        // Classified failures don't register the untagged failure series
+        sink.assert_not_emitted(name, &[OUTCOME_FAILURE_TAG]);
+        // Until a kind can't be registered and falls back to it
+        registry
+            .register_gauge(
+                Metric::from(name).with_tags([OUTCOME_FAILURE_TAG, "error_kind:notfound"]),
+            )
+            .unwrap();
+        let _ = timing
+            .measure_result_async(async { fallible(Some(TestError::NotFound)) })
+            .await;
+        registry.flush();
+        sink.assert_count(name, &[OUTCOME_FAILURE_TAG], 1);
+    }
+}
@@ -0,0 +1,533 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::sink::{Sink, SinkError, SinkResult};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+// How long a scrape may block reading the request or writing the response
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the accept loop checks whether the server was dropped
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// How long dropping the server waits for a scrape in flight before detaching its thread
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_STALE_AFTER: u32 = 1;
+
+/// Sorted `(name, value)` pairs parsed from a series' tags.
+type Labels = Vec<(String, String)>;
+type Families = BTreeMap<String, BTreeMap<Labels, Tracked>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Series {
+    /// Cumulative across emission windows
+    Counter(f64),
+    Gauge(f64),
+    /// Cumulative `_sum` and `_count`
+    Summary {
+        sum: f64,
+        count: f64,
+    },
+}
+
+/// A series and how many emission cycles in a row it has been missing from.
+#[derive(Clone, Copy, Debug)]
+struct Tracked {
+    series: Series,
+    emitted: bool,
+    missed: u32,
+}
+
+impl Series {
+    fn kind(&self) -> Kind {
+        match self {
+            Series::Counter(_) => Kind::Counter,
+            Series::Gauge(_) => Kind::Gauge,
+            Series::Summary { .. } => Kind::Summary,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Summary,
+}
+
+impl Kind {
+    fn type_name(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Summary => "summary",
+        }
+    }
+    /// Counters are named with their `_total` suffix so the `# TYPE` line matches the samples.
+    fn family_name(self, name: &str) -> String {
+        let name = sanitize_name(name, true);
+        match self {
+            Kind::Counter => format!("{name}_total"),
+            Kind::Gauge | Kind::Summary => name,
+        }
+    }
+}
+
+/// [Sink] keeping the latest state of everything a [MetricsRegistry](crate::MetricsRegistry)
+/// emits and rendering it in the Prometheus text exposition format.
+///
+/// Counts become cumulative counters suffixed with `_total`, [TimingCount](crate::instrument::TimingCount)s
+/// and distribution samples become summaries with `_sum` and `_count`, everything else is a gauge.
+/// Dots and other characters Prometheus doesn't allow in names are replaced with `_`, and tags are
+/// split on the first `:` into labels, tags without a value get the value `true`. A name can only
+/// hold one kind, emitting a different kind under a name that's already taken fails with
+/// [SinkError::Other].
+///
+/// Values only change when the registry emits, so scrapes lag behind by up to one observation period.
+/// Series the registry stops emitting, because they were unregistered, expired or skipped by an
+/// [EmissionPolicy](crate::EmissionPolicy), are dropped at the end of the cycle they're missing from,
+/// see [PrometheusExporter::with_stale_after]. Use a [FanoutSink](crate::sink::FanoutSink) to export
+/// next to DogStatsD.
+#[derive(Debug)]
+pub struct PrometheusExporter {
+    families: Mutex<Families>,
+    stale_after: u32,
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self {
+            families: Mutex::default(),
+            stale_after: DEFAULT_STALE_AFTER,
+        }
+    }
+}
+
+fn sanitize_name(name: &str, allow_colon: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            ':' if allow_colon => c,
+            _ => '_',
+        })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn labels(tags: &[&str]) -> Labels {
+    let mut labels: Labels = tags
+        .iter()
+        .map(|tag| {
+            let (key, value) = tag.split_once(':').unwrap_or((tag, "true"));
+            (sanitize_name(key, false), value.to_string())
+        })
+        .collect();
+    labels.sort();
+    labels
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl PrometheusExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Drops series once they've been missing from `cycles` emission cycles in a row, 1 by default.
+    /// Raise it to the heartbeat of an [EmissionPolicy](crate::EmissionPolicy) that skips unchanged
+    /// values so skipped series stay exported. Counters that come back start over from zero.
+    pub fn with_stale_after(self, cycles: u32) -> Self {
+        Self {
+            stale_after: cycles.max(1),
+            ..self
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, Families> {
+        self.families.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Replaces the series for `name` and `tags` with `f` applied to its current value.
+    fn update<F: FnOnce(Option<Series>) -> Series>(
+        &self,
+        name: &str,
+        kind: Kind,
+        tags: &[&str],
+        f: F,
+    ) -> SinkResult {
+        let mut families = self.lock();
+        let family_name = kind.family_name(name);
+        let family = families.entry(family_name.clone()).or_default();
+        if let Some(existing) = family
+            .values()
+            .map(|tracked| tracked.series.kind())
+            .find(|existing| *existing != kind)
+        {
+            return Err(SinkError::Other(format!(
+                "{name} would be exported as {} {family_name}, which is already a {}",
+                kind.type_name(),
+                existing.type_name()
+            )));
+        }
+        let labels = labels(tags);
+        let series = f(family.get(&labels).map(|tracked| tracked.series));
+        family.insert(
+            labels,
+            Tracked {
+                series,
+                emitted: true,
+                missed: 0,
+            },
+        );
+        Ok(())
+    }
+    fn add_to_summary(&self, name: &str, sum: f64, count: f64, tags: &[&str]) -> SinkResult {
+        self.update(name, Kind::Summary, tags, |series| match series {
+            Some(Series::Summary {
+                sum: total_sum,
+                count: total_count,
+            }) => Series::Summary {
+                sum: total_sum + sum,
+                count: total_count + count,
+            },
+            _ => Series::Summary { sum, count },
+        })
+    }
+    /// Renders every series in the text exposition format, sorted by name and labels.
+    pub fn render(&self) -> String {
+        let families = self.lock();
+        let mut out = String::new();
+        for (name, series) in families.iter() {
+            let Some(first) = series.values().next() else {
+                continue;
+            };
+            let _ = writeln!(out, "# TYPE {name} {}", first.series.kind().type_name());
+            for (labels, tracked) in series {
+                let labels = if labels.is_empty() {
+                    String::new()
+                } else {
+                    let pairs: Vec<String> = labels
+                        .iter()
+                        .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+                        .collect();
+                    format!("{{{}}}", pairs.join(","))
+                };
+                match tracked.series {
+                    Series::Counter(total) => {
+                        let _ = writeln!(out, "{name}{labels} {total}");
+                    }
+                    Series::Gauge(value) => {
+                        let _ = writeln!(out, "{name}{labels} {value}");
+                    }
+                    Series::Summary { sum, count } => {
+                        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+                        let _ = writeln!(out, "{name}_count{labels} {count}");
+                    }
+                }
+            }
+        }
+        out
+    }
+    /// Serves [PrometheusExporter::render] on `GET /metrics` from a background thread,
+    /// which stops when the returned [MetricsServer] is dropped.
+    pub fn serve<A: ToSocketAddrs>(self: &Arc<Self>, addr: A) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (exporter, stop) = (self.clone(), stop.clone());
+            std::thread::Builder::new()
+                .name("gnort-prometheus".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let result = match listener.accept() {
+                            Ok((stream, _)) => exporter.respond(stream),
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                                continue;
+                            }
+                            Err(err) => Err(err),
+                        };
+                        let _ = result
+                            .map_err(|err| debug!("Failed to serve Prometheus scrape, was: {err}"));
+                    }
+                })?
+        };
+        Ok(MetricsServer {
+            local_addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        // Accepted streams may inherit the listener's non-blocking mode
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Drain the headers so closing the connection doesn't reset it before the response is read
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            _ => ("404 Not Found", String::new()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+/// Counts, timings and distributions accumulate across emission windows instead of being reset.
+impl Sink for PrometheusExporter {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult {
+        self.update(name, Kind::Counter, tags, |series| match series {
+            Some(Series::Counter(total)) => Series::Counter(total + value as f64),
+            _ => Series::Counter(value as f64),
+        })
+    }
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult {
+        self.update(name, Kind::Gauge, tags, |_| Series::Gauge(value))
+    }
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult {
+        self.add_to_summary(name, milliseconds as f64, 1.0, tags)
+    }
+    /// Set members have no Prometheus equivalent and are dropped, the registry's
+    /// [Set](crate::instrument::Set) instrument emits its estimate as a gauge instead.
+    fn set(&self, _name: &str, _value: &str, _tags: &[&str]) -> SinkResult {
+        Ok(())
+    }
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult {
+        self.add_to_summary(name, values.iter().sum(), values.len() as f64, tags)
+    }
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        let sum = bins
+            .iter()
+            .map(|(value, count)| value * *count as f64)
+            .sum();
+        let count = bins.iter().map(|(_, count)| *count as f64).sum();
+        self.add_to_summary(name, sum, count, tags)
+    }
+    fn timing_count(&self, name: &str, sum: i64, count: i64, tags: &[&str]) -> SinkResult {
+        self.add_to_summary(name, sum as f64, count as f64, tags)
+    }
+    /// Ends the emission cycle, dropping series that have gone stale.
+    fn flush(&self) -> SinkResult {
+        let mut families = self.lock();
+        for family in families.values_mut() {
+            family.retain(|_, tracked| {
+                if std::mem::take(&mut tracked.emitted) {
+                    tracked.missed = 0;
+                } else {
+                    tracked.missed += 1;
+                }
+                tracked.missed < self.stale_after
+            });
+        }
+        families.retain(|_, family| !family.is_empty());
+        Ok(())
+    }
+}
+
+/// HTTP listener started by [PrometheusExporter::serve], stops when dropped. Dropping waits
+/// briefly for a scrape in flight, a stalled one finishes on its own once it times out.
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Address the listener is bound to, useful when serving on port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                debug!("Prometheus listener is still serving a scrape, detaching it");
+                return;
+            }
+            std::thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, time::Duration};
+
+    use super::*;
+    use crate::{
+        sink::FanoutSink, testing::RecordingSink, EmissionMode, Metric, MetricType,
+        MetricsRegistry, RegistryConfig,
+    };
+
+    fn scrape(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_render_registry() {
+        let exporter = Arc::new(PrometheusExporter::new());
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_sink(exporter.clone())
+                .with_emission(EmissionMode::Manual),
+        );
+        let count = registry
+            .register_count(Metric::from("gnort.requests").with_tags(["route:/users", "canary"]))
+            .unwrap();
+        let gauge = registry.register_gauge("gnort.queue.depth").unwrap();
+        let timing = registry.register_timing_count("gnort.latency").unwrap();
+        count.fetch_add(2);
+        gauge.swap(4.5);
+        timing.add_timing(&Duration::from_millis(30));
+        registry.flush();
+        count.fetch_add(3);
+        timing.add_timing(&Duration::from_millis(10));
+        registry.flush();
+        let rendered = exporter.render();
+        // Counts are cumulative across windows, not reset like in DogStatsD
+        assert!(rendered.contains(
+            "# TYPE gnort_requests_total counter\ngnort_requests_total{canary=\"true\",route=\"/users\"} 5\n"
+        ));
+        assert!(rendered.contains("# TYPE gnort_queue_depth gauge\ngnort_queue_depth 4.5\n"));
+        assert!(rendered.contains(
+            "# TYPE gnort_latency summary\ngnort_latency_sum 40\ngnort_latency_count 2\n"
+        ));
+    }
+
+    #[test]
+    fn test_drop_stale_series() {
+        let exporter = Arc::new(PrometheusExporter::new());
+        let recording = Arc::new(RecordingSink::default());
+        let fanout = FanoutSink::new([exporter.clone() as Arc<dyn Sink>, recording.clone()]);
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_sink(Arc::new(fanout))
+                .with_emission(EmissionMode::Manual),
+        );
+        let count = registry.register_count("gnort.requests").unwrap();
+        registry.register_gauge("gnort.queue.depth").unwrap();
+        count.fetch_add(2);
+        registry.flush();
+        recording.assert_count("gnort.requests", &[], 2);
+        assert!(exporter.render().contains("gnort_requests_total 2\n"));
+        assert!(registry.unregister::<MetricType::Count, _>("gnort.requests"));
+        registry.flush();
+        let rendered = exporter.render();
+        assert!(!rendered.contains("gnort_requests_total"));
+        assert!(rendered.contains("# TYPE gnort_queue_depth gauge\ngnort_queue_depth 0\n"));
+    }
+
+    #[test]
+    fn test_stale_after() {
+        let exporter = PrometheusExporter::new().with_stale_after(2);
+        exporter.count("gnort.requests", 2, &[]).unwrap();
+        exporter.flush().unwrap();
+        exporter.flush().unwrap();
+        assert_eq!(
+            exporter.render(),
+            "# TYPE gnort_requests_total counter\ngnort_requests_total 2\n"
+        );
+        exporter.count("gnort.requests", 1, &[]).unwrap();
+        exporter.flush().unwrap();
+        exporter.flush().unwrap();
+        exporter.flush().unwrap();
+        assert_eq!(exporter.render(), "");
+    }
+
+    #[test]
+    fn test_kind_collision() {
+        let exporter = PrometheusExporter::new();
+        exporter.gauge("gnort.requests", 1.0, &[]).unwrap();
+        assert!(exporter.timing("gnort.requests", 10, &[]).is_err());
+        exporter.count("gnort.requests", 1, &[]).unwrap();
+        assert!(exporter.gauge("gnort.requests_total", 1.0, &[]).is_err());
+        assert_eq!(
+            exporter.render(),
+            "# TYPE gnort_requests gauge\ngnort_requests 1\n\
+             # TYPE gnort_requests_total counter\ngnort_requests_total 1\n"
+        );
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let exporter = Arc::new(PrometheusExporter::new());
+        Sink::gauge(exporter.as_ref(), "gnort.up", 1.0, &["env:test"]).unwrap();
+        let server = exporter.serve("127.0.0.1:0").unwrap();
+        let response = scrape(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n# TYPE gnort_up gauge\ngnort_up{env=\"test\"} 1\n"));
+        let response = scrape(server.local_addr(), "/");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_drop_with_stalled_scrape() {
+        let exporter = Arc::new(PrometheusExporter::new());
+        let server = exporter.serve("127.0.0.1:0").unwrap();
+        // Connects but never sends a request
+        let _stalled = TcpStream::connect(server.local_addr()).unwrap();
+        std::thread::sleep(ACCEPT_POLL_INTERVAL * 5);
+        let start = Instant::now();
+        drop(server);
+        assert!(start.elapsed() < SCRAPE_TIMEOUT);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize_name("gnort.http-requests", true),
+            "gnort_http_requests"
+        );
+        assert_eq!(sanitize_name("2xx", false), "_2xx");
+        assert_eq!(
+            labels(&["path:a:b", "user-id:\"x\""]),
+            vec![
+                ("path".to_string(), "a:b".to_string()),
+                ("user_id".to_string(), "\"x\"".to_string()),
+            ]
+        );
+        assert_eq!(escape_label_value("\"x\"\n"), "\\\"x\\\"\\n");
+    }
+}
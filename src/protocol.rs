@@ -0,0 +1,529 @@
+use std::{
+    fmt::{Display, Write},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+/// DogStatsD metric types, see [MetricKind::as_str] for their wire format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MetricKind {
+    Count,
+    Gauge,
+    Timing,
+    Histogram,
+    Set,
+    Distribution,
+}
+
+impl MetricKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Count => "c",
+            MetricKind::Gauge => "g",
+            MetricKind::Timing => "ms",
+            MetricKind::Histogram => "h",
+            MetricKind::Set => "s",
+            MetricKind::Distribution => "d",
+        }
+    }
+}
+
+impl FromStr for MetricKind {
+    type Err = ();
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "c" => Ok(MetricKind::Count),
+            "g" => Ok(MetricKind::Gauge),
+            "ms" => Ok(MetricKind::Timing),
+            "h" => Ok(MetricKind::Histogram),
+            "s" => Ok(MetricKind::Set),
+            "d" => Ok(MetricKind::Distribution),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Optional fields of a metric line.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MetricOptions {
+    /// `|@<rate>` (protocol v1.0), only written when below 1.
+    pub sample_rate: Option<f64>,
+    /// `|T<unix seconds>` (protocol v1.3), the agent attributes the value to this time instead of
+    /// when it was received. Only supported for counts and gauges.
+    pub timestamp: Option<u64>,
+}
+
+impl MetricOptions {
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Formats DogStatsD lines with a client's namespace, default tags and container ID.
+///
+/// Metrics are written as `namespace.name:value[:value..]|type|@rate|#tags|c:container|T<timestamp>`,
+/// packing several values into one line (protocol v1.1) for distributions, histograms and timings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Encoder {
+    namespace: String,
+    default_tags: Vec<String>,
+    container_id: Option<String>,
+}
+
+impl Encoder {
+    /// `default_tags` are appended to every line after the line's own tags.
+    pub fn new(namespace: &str, default_tags: Vec<String>) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            default_tags,
+            container_id: None,
+        }
+    }
+    /// Sent as the `|c:<container_id>` field (protocol v1.2) so the agent can tag metrics with
+    /// the container they came from when it can't detect it from the socket.
+    pub fn with_container_id(mut self, container_id: impl Into<String>) -> Self {
+        self.container_id = Some(container_id.into());
+        self
+    }
+    /// Appends one metric line to `buf`, `values` must not be empty.
+    pub fn encode_metric<V, I, T>(
+        &self,
+        buf: &mut String,
+        name: &str,
+        values: &[V],
+        kind: MetricKind,
+        tags: I,
+        options: MetricOptions,
+    ) where
+        V: Display,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        debug_assert!(!values.is_empty(), "metric {name} has no values");
+        if !self.namespace.is_empty() {
+            buf.push_str(&self.namespace);
+            buf.push('.');
+        }
+        buf.push_str(name);
+        for value in values {
+            // Writing to a String can't fail
+            let _ = write!(buf, ":{value}");
+        }
+        buf.push('|');
+        buf.push_str(kind.as_str());
+        if let Some(sample_rate) = options.sample_rate.filter(|rate| *rate < 1.0) {
+            let _ = write!(buf, "|@{sample_rate}");
+        }
+        self.encode_tags(buf, tags);
+        self.encode_container_id(buf);
+        if let Some(timestamp) = options.timestamp {
+            let _ = write!(buf, "|T{timestamp}");
+        }
+    }
+    /// Appends an event line to `buf`. Events aren't namespaced and newlines in `text` are escaped.
+    pub fn encode_event<I, T>(&self, buf: &mut String, title: &str, text: &str, tags: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let text = text.replace('\n', "\\n");
+        let _ = write!(
+            buf,
+            "_e{{{},{}}}:{}|{}",
+            title.len(),
+            text.len(),
+            title,
+            text
+        );
+        self.encode_tags(buf, tags);
+        self.encode_container_id(buf);
+    }
+    fn encode_tags<I, T>(&self, buf: &mut String, tags: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut separator = "|#";
+        for tag in tags {
+            buf.push_str(separator);
+            buf.push_str(tag.as_ref());
+            separator = ",";
+        }
+        for tag in &self.default_tags {
+            buf.push_str(separator);
+            buf.push_str(tag);
+            separator = ",";
+        }
+    }
+    fn encode_container_id(&self, buf: &mut String) {
+        if let Some(container_id) = &self.container_id {
+            buf.push_str("|c:");
+            buf.push_str(container_id);
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Malformed DogStatsD line {line:?}, {reason}")]
+pub struct ParseError {
+    pub line: String,
+    pub reason: &'static str,
+}
+
+/// A metric line decoded by [parse_line].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedMetric {
+    /// Including the namespace
+    pub name: String,
+    /// As written, sets have arbitrary string values
+    pub values: Vec<String>,
+    pub kind: MetricKind,
+    pub sample_rate: Option<f64>,
+    pub tags: Vec<String>,
+    pub container_id: Option<String>,
+    pub timestamp: Option<u64>,
+}
+
+impl ParsedMetric {
+    /// Numeric values, `None` if any of them isn't a number.
+    pub fn float_values(&self) -> Option<Vec<f64>> {
+        self.values.iter().map(|value| value.parse().ok()).collect()
+    }
+}
+
+/// An event line decoded by [parse_line], `text` is unescaped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedEvent {
+    pub title: String,
+    pub text: String,
+    pub timestamp: Option<u64>,
+    pub hostname: Option<String>,
+    pub aggregation_key: Option<String>,
+    pub priority: Option<String>,
+    pub source_type: Option<String>,
+    pub alert_type: Option<String>,
+    pub tags: Vec<String>,
+    pub container_id: Option<String>,
+}
+
+/// A service check line decoded by [parse_line].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedServiceCheck {
+    pub name: String,
+    /// 0 OK, 1 warning, 2 critical, 3 unknown
+    pub status: u8,
+    pub timestamp: Option<u64>,
+    pub hostname: Option<String>,
+    pub tags: Vec<String>,
+    pub message: Option<String>,
+    pub container_id: Option<String>,
+}
+
+/// Any line the agent accepts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Metric(ParsedMetric),
+    Event(ParsedEvent),
+    ServiceCheck(ParsedServiceCheck),
+}
+
+/// Decodes every newline separated line of a datagram, skipping empty lines.
+pub fn parse_packet(packet: &str) -> Result<Vec<Message>, ParseError> {
+    packet
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+/// Decodes a single metric, event or service check line. Fields the protocol doesn't
+/// define are rejected rather than ignored, so encoding mistakes show up in tests.
+pub fn parse_line(line: &str) -> Result<Message, ParseError> {
+    let malformed = |reason| ParseError {
+        line: line.to_string(),
+        reason,
+    };
+    if let Some(rest) = line.strip_prefix("_e{") {
+        parse_event(rest).map(Message::Event).map_err(malformed)
+    } else if let Some(rest) = line.strip_prefix("_sc|") {
+        parse_service_check(rest)
+            .map(Message::ServiceCheck)
+            .map_err(malformed)
+    } else {
+        parse_metric(line).map(Message::Metric).map_err(malformed)
+    }
+}
+
+fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',').map(str::to_string).collect()
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<u64, &'static str> {
+    timestamp.parse().map_err(|_| "invalid timestamp")
+}
+
+fn parse_metric(line: &str) -> Result<ParsedMetric, &'static str> {
+    let mut fields = line.split('|');
+    let (name, values) = fields
+        .next()
+        .and_then(|field| field.split_once(':'))
+        .ok_or("expected name:value")?;
+    if name.is_empty() {
+        return Err("name is empty");
+    }
+    let values: Vec<String> = values.split(':').map(str::to_string).collect();
+    if values.iter().any(String::is_empty) {
+        return Err("value is empty");
+    }
+    let kind = fields
+        .next()
+        .ok_or("missing metric type")?
+        .parse()
+        .map_err(|_| "unknown metric type")?;
+    let mut metric = ParsedMetric {
+        name: name.to_string(),
+        values,
+        kind,
+        sample_rate: None,
+        tags: vec![],
+        container_id: None,
+        timestamp: None,
+    };
+    for field in fields {
+        if let Some(rate) = field.strip_prefix('@') {
+            metric.sample_rate = Some(rate.parse().map_err(|_| "invalid sample rate")?);
+        } else if let Some(tags) = field.strip_prefix('#') {
+            metric.tags = parse_tags(tags);
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            metric.container_id = Some(container_id.to_string());
+        } else if let Some(timestamp) = field.strip_prefix('T') {
+            metric.timestamp = Some(parse_timestamp(timestamp)?);
+        } else {
+            return Err("unknown field");
+        }
+    }
+    Ok(metric)
+}
+
+fn parse_event(rest: &str) -> Result<ParsedEvent, &'static str> {
+    let (lengths, rest) = rest.split_once("}:").ok_or("expected _e{title,text}:")?;
+    let (title_len, text_len) = lengths
+        .split_once(',')
+        .and_then(|(title, text)| Some((title.parse().ok()?, text.parse().ok()?)))
+        .ok_or("invalid lengths")?;
+    // Lengths are in bytes and title and text may contain '|'
+    let title = rest
+        .get(..title_len)
+        .ok_or("title is shorter than its length")?;
+    let rest = rest[title_len..]
+        .strip_prefix('|')
+        .ok_or("expected | after title")?;
+    let text = rest
+        .get(..text_len)
+        .ok_or("text is shorter than its length")?;
+    let mut event = ParsedEvent {
+        title: title.to_string(),
+        text: text.replace("\\n", "\n"),
+        ..Default::default()
+    };
+    let rest = &rest[text_len..];
+    if rest.is_empty() {
+        return Ok(event);
+    }
+    let rest = rest.strip_prefix('|').ok_or("expected | after text")?;
+    for field in rest.split('|') {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            event.timestamp = Some(parse_timestamp(timestamp)?);
+        } else if let Some(hostname) = field.strip_prefix("h:") {
+            event.hostname = Some(hostname.to_string());
+        } else if let Some(key) = field.strip_prefix("k:") {
+            event.aggregation_key = Some(key.to_string());
+        } else if let Some(priority) = field.strip_prefix("p:") {
+            event.priority = Some(priority.to_string());
+        } else if let Some(source_type) = field.strip_prefix("s:") {
+            event.source_type = Some(source_type.to_string());
+        } else if let Some(alert_type) = field.strip_prefix("t:") {
+            event.alert_type = Some(alert_type.to_string());
+        } else if let Some(tags) = field.strip_prefix('#') {
+            event.tags = parse_tags(tags);
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            event.container_id = Some(container_id.to_string());
+        } else {
+            return Err("unknown field");
+        }
+    }
+    Ok(event)
+}
+
+fn parse_service_check(rest: &str) -> Result<ParsedServiceCheck, &'static str> {
+    let mut fields = rest.split('|');
+    let name = fields.next().filter(|name| !name.is_empty());
+    let mut check = ParsedServiceCheck {
+        name: name.ok_or("name is empty")?.to_string(),
+        status: fields
+            .next()
+            .and_then(|status| status.parse().ok())
+            .filter(|status| *status <= 3)
+            .ok_or("status must be 0 to 3")?,
+        ..Default::default()
+    };
+    while let Some(field) = fields.next() {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            check.timestamp = Some(parse_timestamp(timestamp)?);
+        } else if let Some(hostname) = field.strip_prefix("h:") {
+            check.hostname = Some(hostname.to_string());
+        } else if let Some(tags) = field.strip_prefix('#') {
+            check.tags = parse_tags(tags);
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            check.container_id = Some(container_id.to_string());
+        } else if let Some(message) = field.strip_prefix("m:") {
+            // The message is the last field and runs to the end of the line
+            let rest: Vec<&str> = fields.by_ref().collect();
+            let message = std::iter::once(message).chain(rest).collect::<Vec<_>>();
+            check.message = Some(message.join("|"));
+        } else {
+            return Err("unknown field");
+        }
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_metric(line: &str) -> ParsedMetric {
+        match parse_line(line) {
+            Ok(Message::Metric(metric)) => metric,
+            other => panic!("expected a metric, was: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_encode_metric_round_trip() {
+        let encoder = Encoder::new("gnort", vec!["env:test".to_string()]).with_container_id("abc");
+        let mut buf = String::new();
+        encoder.encode_metric(
+            &mut buf,
+            "requests",
+            &[1.5, 2.0, 0.25],
+            MetricKind::Distribution,
+            ["route:/users"],
+            MetricOptions::default()
+                .with_sample_rate(0.5)
+                .with_timestamp(1_700_000_000),
+        );
+        assert_eq!(
+            buf,
+            "gnort.requests:1.5:2:0.25|d|@0.5|#route:/users,env:test|c:abc|T1700000000"
+        );
+        let metric = parse_metric(&buf);
+        assert_eq!(
+            metric,
+            ParsedMetric {
+                name: "gnort.requests".to_string(),
+                values: vec!["1.5".to_string(), "2".to_string(), "0.25".to_string()],
+                kind: MetricKind::Distribution,
+                sample_rate: Some(0.5),
+                tags: vec!["route:/users".to_string(), "env:test".to_string()],
+                container_id: Some("abc".to_string()),
+                timestamp: Some(1_700_000_000),
+            }
+        );
+        assert_eq!(metric.float_values(), Some(vec![1.5, 2.0, 0.25]));
+    }
+
+    #[test]
+    fn test_encode_metric_minimal() {
+        let encoder = Encoder::default();
+        for (kind, expected) in [
+            (MetricKind::Count, "hits:3|c"),
+            (MetricKind::Gauge, "hits:3|g"),
+            (MetricKind::Timing, "hits:3|ms"),
+            (MetricKind::Histogram, "hits:3|h"),
+            (MetricKind::Set, "hits:3|s"),
+        ] {
+            let mut buf = String::new();
+            // A sample rate of 1 is the default and isn't written
+            let options = MetricOptions::default().with_sample_rate(1.0);
+            encoder.encode_metric(&mut buf, "hits", &[3], kind, [] as [&str; 0], options);
+            assert_eq!(buf, expected);
+            assert_eq!(parse_metric(&buf).kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_encode_event() {
+        let encoder = Encoder::new("gnort", vec![]);
+        let mut buf = String::new();
+        encoder.encode_event(&mut buf, "deployed", "v2\nrolled out", ["team:metrics"]);
+        assert_eq!(buf, "_e{8,14}:deployed|v2\\nrolled out|#team:metrics");
+    }
+
+    #[test]
+    fn test_event_round_trip() {
+        let encoder = Encoder::new("gnort", vec![]).with_container_id("abc");
+        let mut buf = String::new();
+        encoder.encode_event(&mut buf, "a|b", "multi\nline", ["team:metrics"]);
+        assert_eq!(
+            parse_packet(&buf).unwrap(),
+            vec![Message::Event(ParsedEvent {
+                title: "a|b".to_string(),
+                text: "multi\nline".to_string(),
+                tags: vec!["team:metrics".to_string()],
+                container_id: Some("abc".to_string()),
+                ..Default::default()
+            })]
+        );
+        let line = "_e{5,4}:title|text|d:1700000000|h:web-1|k:deploy|p:low|s:rust|t:warning";
+        match parse_line(line).unwrap() {
+            Message::Event(event) => {
+                assert_eq!(event.timestamp, Some(1_700_000_000));
+                assert_eq!(event.hostname.as_deref(), Some("web-1"));
+                assert_eq!(event.alert_type.as_deref(), Some("warning"));
+            }
+            other => panic!("expected an event, was: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_service_check() {
+        let line = "_sc|gnort.up|2|d:1700000000|h:web-1|#env:test|m:down | badly";
+        assert_eq!(
+            parse_line(line).unwrap(),
+            Message::ServiceCheck(ParsedServiceCheck {
+                name: "gnort.up".to_string(),
+                status: 2,
+                timestamp: Some(1_700_000_000),
+                hostname: Some("web-1".to_string()),
+                tags: vec!["env:test".to_string()],
+                message: Some("down | badly".to_string()),
+                container_id: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (line, reason) in [
+            ("gnort.count", "expected name:value"),
+            ("gnort.count:1", "missing metric type"),
+            ("gnort.count:1|x", "unknown metric type"),
+            ("gnort.count:1|c|@fast", "invalid sample rate"),
+            ("gnort.count:1|c|?", "unknown field"),
+            ("_e{10,1}:short|x", "title is shorter than its length"),
+            ("_sc|gnort.up|7", "status must be 0 to 3"),
+        ] {
+            assert_eq!(parse_line(line).unwrap_err().reason, reason, "{line}");
+        }
+        let packet = "gnort.a:1|c\n\ngnort.b:2|g\n";
+        assert_eq!(parse_packet(packet).unwrap().len(), 2);
+    }
+}
@@ -1,21 +1,36 @@
 use std::{
+    collections::BTreeSet,
     num::NonZeroU32,
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    time::Duration,
 };
 
 use dashmap::DashMap;
 use governor::{
-    clock::{Clock, DefaultClock},
-    DefaultDirectRateLimiter, Quota, RateLimiter,
+    middleware::NoOpMiddleware,
+    state::{InMemoryState, NotKeyed},
+    Quota, RateLimiter,
 };
+use maplit::btreeset;
 use nonzero_ext::nonzero;
 use thiserror::Error;
 use tracing::{debug, trace};
 
 use crate::{
     client::{sync_client, GnortClient},
-    instrument::{Count, Gauge, Instrument, TimingCount},
+    clock::{Clock, GovernorClock, SystemClock},
+    emitter::{self, Emitter, Schedule},
+    family::{CountFamily, GaugeFamily, TimingCountFamily},
+    instrument::{
+        Count, Distribution, Gauge, GaugeAggregation, Histogram, Instrument, Set, TimingCount,
+        TopK, UpDownCounter,
+    },
+    metric::intern_name,
+    outcome::OutcomeTimingCount,
+    sink::Sink,
     MakeInstrument, Metric, MetricKey, MetricType,
 };
 use once_cell::sync::OnceCell;
@@ -25,12 +40,18 @@ use once_cell::sync::OnceCell;
 /// linger time set.
 static GLOBAL_BUCKET: OnceCell<MetricsRegistry> = OnceCell::new();
 static TIME_TO_EMIT_METRICS: &str = "gnort.aggregate.time_to_emit_metrics.gauge";
+/// Counts registrations redirected to the overflow series, tagged with `metric:<name>`
+pub const REJECTED_SERIES_METRIC: &str = "gnort.registry.rejected_series";
+/// Tag of the series that registrations past a series limit are redirected to
+pub const OVERFLOW_TAG: &str = "overflow:true";
 
 pub fn global_metrics_registry() -> &'static MetricsRegistry {
     GLOBAL_BUCKET.get_or_init(|| MetricsRegistry::new(Default::default()))
 }
 
 type MetricsMap = Arc<DashMap<MetricKey, Instrument>>;
+type RegistryRateLimiter =
+    RateLimiter<NotKeyed, InMemoryState, GovernorClock, NoOpMiddleware<Duration>>;
 fn new_metric_map() -> MetricsMap {
     Arc::new(DashMap::new())
 }
@@ -42,16 +63,156 @@ pub struct MetricsRegistry {
     /// Concurrent HashMap (DashMap) of metrics keyed to their associated instruments.
     // TODO: We need to benchmark/profile interning metric (stat) names and tag keys
     pub(crate) metrics: MetricsMap,
-    /// client is optional because the registry can fallback to the global registry.
+    /// sink is optional because the registry can fallback to the global client.
     /// This could impact default tags are used.
-    client: Option<GnortClient>,
-    // How often should the metric be emitted? Default is 10 seconds
-    // Skipping this for now in lieu of a standard aggregation time.
-    observation_period: Option<Duration>,
-    // What delay should the metrical client use before emitting the first observation?
-    delay_time: Option<Duration>,
+    sink: Option<Arc<dyn Sink>>,
     // Rate limiter
-    rate_limiter: Arc<governor::DefaultDirectRateLimiter>,
+    rate_limiter: Arc<RegistryRateLimiter>,
+    // Drives the rate limiter and the manual emission schedule
+    clock: Arc<dyn Clock>,
+    // When emission cycles are due according to `clock`
+    pub(crate) schedule: Arc<Schedule>,
+    // Deadline of the next cycle for [MetricsRegistry::flush_if_due]
+    next_emission: Arc<Mutex<Duration>>,
+    // Lets shutdown wake up, stop and join the emitter thread or task
+    pub(crate) emitter: Arc<Emitter>,
+    limits: SeriesLimits,
+    // Series registered so far, checked against `limits`
+    series: Arc<SeriesCounts>,
+    // Number of completed emission cycles, instruments record the generation they were last updated in
+    generation: Arc<AtomicU64>,
+    idle_expiry: Option<NonZeroU32>,
+    emission_policy: EmissionPolicy,
+    // Applied to every registration, see [MetricsRegistry::scoped]
+    scope: Option<Arc<Scope>>,
+    // Evicted and unregistered instruments whose handles are still held, registering one of
+    // them again returns it so the handles and the registry keep sharing its window
+    evicted: MetricsMap,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    prefix: String,
+    tags: BTreeSet<String>,
+    // Prefixed names by unprefixed name, so registering through the scope only interns each name once
+    names: DashMap<&'static str, &'static str>,
+}
+
+impl Scope {
+    fn prefixed(&self, name: &'static str) -> &'static str {
+        *self
+            .names
+            .entry(name)
+            .or_insert_with(|| intern_name(format!("{}{}", self.prefix, name)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SeriesLimits {
+    per_metric: Option<usize>,
+    total: Option<usize>,
+}
+
+/// Overflow series and the rejected series self-metric aren't counted.
+#[derive(Debug, Default)]
+struct SeriesCounts {
+    total: AtomicUsize,
+    per_metric: DashMap<&'static str, usize>,
+}
+
+impl SeriesCounts {
+    /// Counts a new series of `name` unless that would exceed `limits`.
+    fn try_admit(&self, name: &'static str, limits: SeriesLimits) -> bool {
+        let mut per_metric = self.per_metric.entry(name).or_insert(0);
+        if limits.per_metric.is_some_and(|limit| *per_metric >= limit) {
+            return false;
+        }
+        let admitted = self
+            .total
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                match limits.total {
+                    Some(limit) if total >= limit => None,
+                    _ => Some(total + 1),
+                }
+            })
+            .is_ok();
+        if admitted {
+            *per_metric += 1;
+        }
+        admitted
+    }
+    /// Counts a series that was evicted and is put back, regardless of the limits.
+    fn readmit(&self, name: &'static str) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        *self.per_metric.entry(name).or_insert(0) += 1;
+    }
+    fn release(&self, name: &'static str) {
+        self.total.fetch_sub(1, Ordering::Relaxed);
+        if let Some(mut per_metric) = self.per_metric.get_mut(name) {
+            *per_metric = per_metric.saturating_sub(1);
+        }
+    }
+}
+
+/// Series an instrument is registered as, so it can put itself back into the registry when
+/// it's updated after being evicted or unregistered.
+#[derive(Debug)]
+pub(crate) struct SeriesBinding {
+    key: MetricKey,
+    // Whether the series counts against the registry's series limits
+    counted: bool,
+    generation: Arc<AtomicU64>,
+    metrics: Weak<DashMap<MetricKey, Instrument>>,
+    evicted: Weak<DashMap<MetricKey, Instrument>>,
+    series: Weak<SeriesCounts>,
+}
+
+impl SeriesBinding {
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+    /// Re-inserts `instrument` unless the registry is gone or the series was registered again in
+    /// the meantime, which only returns a different instrument when the metric type changed.
+    pub(crate) fn reinsert(&self, instrument: Instrument) {
+        let Some(metrics) = self.metrics.upgrade() else {
+            return;
+        };
+        let entry = metrics.entry(self.key.clone());
+        if let dashmap::mapref::entry::Entry::Vacant(vacant) = entry {
+            vacant.insert(instrument);
+            self.readmit();
+            if let Some(evicted) = self.evicted.upgrade() {
+                evicted.remove(&self.key);
+            }
+        }
+    }
+    fn readmit(&self) {
+        if let (true, Some(series)) = (self.counted, self.series.upgrade()) {
+            series.readmit(self.key.get_name());
+        }
+    }
+    fn release(&self) {
+        if let (true, Some(series)) = (self.counted, self.series.upgrade()) {
+            series.release(self.key.get_name());
+        }
+    }
+}
+
+/// Stops the registry's emitter with a final flush when dropped,
+/// see [MetricsRegistry::shutdown_guard].
+#[must_use = "the registry is shut down as soon as the guard is dropped"]
+pub struct ShutdownGuard {
+    registry: MetricsRegistry,
+    timeout: Duration,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        let _ = self
+            .registry
+            .shutdown(self.timeout)
+            .map_err(|err| debug!("Failed to shut down metrics registry, was: {err}"));
+    }
 }
 
 // Client-side aggregation followed by agent aggregation may result in some undesirable effects like
@@ -59,20 +220,115 @@ pub struct MetricsRegistry {
 //      * or having data points from multiple agent's aggregation windows submitted in a single aggregation window
 //      * (that'd make e.g. count metric values from consecutive agg windows differ by ~100%).
 //      * To avoid that, we're using a default observation period of 3 seconds for all metrics.
+//      * WindowAlignment::Epoch additionally lines windows up with the agent's own.
 const DEFAULT_OBSERVATION_PERIOD_MILLIS: u64 = 3_000;
 const OBSERVATION_PERIOD_MILLIS_ENV_VAR: &str = "GNORT_OBSERVATION_PERIOD_MILLIS";
 const DEFAULT_DELAY_MILLIS: u64 = 3_000;
 const DELAY_MILLIS_ENV_VAR: &str = "GNORT_DELAY_MILLIS";
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 const DEFAULT_RATE_LIMIT_PER_SECOND: NonZeroU32 = nonzero!(42_000u32);
 const DEFAULT_BURST_LIMIT: NonZeroU32 = nonzero!(42u32);
 
+/// How a [MetricsRegistry]'s emission cycles are driven.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmissionMode {
+    /// A background thread emits every observation period.
+    #[default]
+    Background,
+    /// Nothing is emitted until you call [MetricsRegistry::flush] or [MetricsRegistry::flush_if_due],
+    /// e.g. from your own scheduler.
+    Manual,
+}
+
+/// Where a [MetricsRegistry]'s observation windows start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowAlignment {
+    /// The first window ends `delay_time` after the registry is created.
+    #[default]
+    Relative,
+    /// Windows start on multiples of the observation period since the UNIX epoch, shifted by
+    /// `offset`, so they line up with the agent's flush interval and with other hosts.
+    /// `delay_time` is ignored, the first window is cut short at the next boundary.
+    Epoch { offset: Duration },
+}
+
+impl WindowAlignment {
+    pub fn epoch() -> Self {
+        WindowAlignment::Epoch {
+            offset: Duration::ZERO,
+        }
+    }
+    /// Epoch alignment offset by a stable per-host amount below `max_jitter`, derived from the
+    /// host name, so a fleet doesn't emit all at once.
+    pub fn epoch_with_host_jitter(max_jitter: Duration) -> Self {
+        let max_nanos = max_jitter.as_nanos().max(1) as u64;
+        let host = host_name().unwrap_or_default();
+        WindowAlignment::Epoch {
+            offset: Duration::from_nanos(crate::sketch::hash_value(&host) % max_nanos),
+        }
+    }
+}
+
+/// Which windows a [MetricsRegistry] leaves out, set for the whole registry with
+/// [RegistryConfig::with_emission_policy] or per series with [MetricsRegistry::set_emission_policy].
+/// Everything is emitted by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EmissionPolicy {
+    /// Skip counts and timing counts of zero, and the `.count` of histograms and distributions without observations
+    pub skip_zero_counts: bool,
+    /// Skip gauges, up-down counters and sets that weren't updated since the last window
+    pub skip_unchanged_gauges: bool,
+    /// Emit skipped series anyway once this many windows passed since they were last emitted,
+    /// so dashboards still see that they exist
+    pub heartbeat: Option<NonZeroU32>,
+}
+
+impl EmissionPolicy {
+    pub fn with_skip_zero_counts(mut self, skip_zero_counts: bool) -> Self {
+        self.skip_zero_counts = skip_zero_counts;
+        self
+    }
+    pub fn with_skip_unchanged_gauges(mut self, skip_unchanged_gauges: bool) -> Self {
+        self.skip_unchanged_gauges = skip_unchanged_gauges;
+        self
+    }
+    pub fn with_heartbeat(mut self, windows: NonZeroU32) -> Self {
+        self.heartbeat = Some(windows);
+        self
+    }
+}
+
+fn host_name() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
+
 #[derive(Clone, Default)]
 pub struct RegistryConfig {
     pub client: Option<GnortClient>,
+    /// Takes precedence over `client` when both are set
+    pub sink: Option<Arc<dyn Sink>>,
     pub observation_period: Option<Duration>,
     pub delay_time: Option<Duration>,
     pub rate_limit_per_second: Option<NonZeroU32>,
     pub burst_limit: Option<NonZeroU32>,
+    pub emission: EmissionMode,
+    pub alignment: WindowAlignment,
+    /// Defaults to [SystemClock]
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Most series registered under one stat name, further tag combinations are redirected
+    /// to that name's `overflow:true` series. Unlimited by default.
+    pub max_series_per_metric: Option<usize>,
+    /// Most series registered across all stat names, redirected like `max_series_per_metric`.
+    /// Unlimited by default.
+    pub max_series: Option<usize>,
+    /// Series that weren't updated for this many emission cycles are removed from the registry.
+    /// Series are kept forever by default.
+    pub idle_expiry: Option<NonZeroU32>,
+    pub emission_policy: EmissionPolicy,
 }
 
 impl RegistryConfig {
@@ -80,6 +336,57 @@ impl RegistryConfig {
         self.client = Some(client);
         self
     }
+    pub fn with_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+    pub fn with_emission(mut self, emission: EmissionMode) -> Self {
+        self.emission = emission;
+        self
+    }
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+    pub fn with_max_series_per_metric(mut self, max_series_per_metric: usize) -> Self {
+        self.max_series_per_metric = Some(max_series_per_metric);
+        self
+    }
+    pub fn with_max_series(mut self, max_series: usize) -> Self {
+        self.max_series = Some(max_series);
+        self
+    }
+    pub fn with_idle_expiry(mut self, windows: NonZeroU32) -> Self {
+        self.idle_expiry = Some(windows);
+        self
+    }
+    pub fn with_emission_policy(mut self, emission_policy: EmissionPolicy) -> Self {
+        self.emission_policy = emission_policy;
+        self
+    }
+}
+
+// What delay should the metrical client use before emitting the first observation?
+fn get_delay(delay_time: Option<Duration>) -> Duration {
+    delay_time.unwrap_or_else(|| {
+        let delay_millis = get_env_or_fallback(DELAY_MILLIS_ENV_VAR, DEFAULT_DELAY_MILLIS);
+        Duration::from_millis(delay_millis)
+    })
+}
+
+// How often should the metric be emitted?
+fn get_observation_period(observation_period: Option<Duration>) -> Duration {
+    observation_period.unwrap_or_else(|| {
+        let observation_millis = get_env_or_fallback(
+            OBSERVATION_PERIOD_MILLIS_ENV_VAR,
+            DEFAULT_OBSERVATION_PERIOD_MILLIS,
+        );
+        Duration::from_millis(observation_millis)
+    })
 }
 
 fn get_env_or_fallback(env_var: &str, fallback: u64) -> u64 {
@@ -99,6 +406,18 @@ fn get_env_or_fallback(env_var: &str, fallback: u64) -> u64 {
 pub enum MetricRegistrationError {
     #[error("Metric type mismatch, expected: {0:?}, was: {1:?}")]
     TypeMismatch(String, Instrument),
+    #[error("Gauge aggregation mismatch, expected: {0:?}, was: {1:?}")]
+    AggregationMismatch(GaugeAggregation, GaugeAggregation),
+    #[error("Tag value count mismatch, expected: {0}, was: {1}")]
+    TagValuesMismatch(usize, usize),
+}
+
+#[derive(Debug, Error)]
+pub enum ShutdownError {
+    #[error("Timed out after {0:?} waiting for the emitter to flush and stop")]
+    Timeout(Duration),
+    #[error("Emitter panicked")]
+    Panicked,
 }
 
 impl MetricsRegistry {
@@ -108,55 +427,144 @@ impl MetricsRegistry {
             .rate_limit_per_second
             .unwrap_or(DEFAULT_RATE_LIMIT_PER_SECOND);
         let burst_limit = registry_config.burst_limit.unwrap_or(DEFAULT_BURST_LIMIT);
-        let rate_limiter = Arc::new(RateLimiter::direct(
+        let clock = registry_config
+            .clock
+            .unwrap_or_else(|| Arc::new(SystemClock::default()));
+        let rate_limiter = Arc::new(new_rate_limiter(
             Quota::per_second(rate_limit_per_second).allow_burst(burst_limit),
+            clock.clone(),
         ));
+        let schedule = Schedule::new(
+            clock.clone(),
+            registry_config.alignment,
+            get_delay(registry_config.delay_time),
+            get_observation_period(registry_config.observation_period),
+        );
         let registry = Self {
             metrics,
             rate_limiter,
-            client: registry_config.client,
-            observation_period: registry_config.observation_period,
-            delay_time: registry_config.delay_time,
+            sink: registry_config.sink.or_else(|| {
+                registry_config
+                    .client
+                    .map(|client| Arc::new(client) as Arc<dyn Sink>)
+            }),
+            emitter: Arc::new(Emitter::default()),
+            next_emission: Arc::new(Mutex::new(schedule.first())),
+            schedule: Arc::new(schedule),
+            clock,
+            limits: SeriesLimits {
+                per_metric: registry_config.max_series_per_metric,
+                total: registry_config.max_series,
+            },
+            series: Arc::new(SeriesCounts::default()),
+            generation: Arc::new(AtomicU64::new(0)),
+            idle_expiry: registry_config.idle_expiry,
+            emission_policy: registry_config.emission_policy,
+            scope: None,
+            evicted: new_metric_map(),
         };
-        registry.start();
+        if registry_config.emission == EmissionMode::Background {
+            registry
+                .emitter
+                .install(|| emitter::spawn_thread(registry.clone()));
+        }
         registry
     }
-    fn get_client(&self) -> &GnortClient {
-        self.client.as_ref().unwrap_or_else(|| sync_client())
+    /// A view of this registry sharing its metrics, sink and emitter, where every metric registered
+    /// through it has `prefix` prepended to its name and `tags` added, e.g. `registry.scoped("payments.", ["component:payments"])`.
+    /// Scopes nest, prefixes are concatenated and tags accumulate. Flushing or shutting down a scope
+    /// affects the whole registry.
+    pub fn scoped<P, I, S>(&self, prefix: P, tags: I) -> MetricsRegistry
+    where
+        P: AsRef<str>,
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        let mut scope = Scope {
+            prefix: prefix.as_ref().to_string(),
+            tags: tags
+                .into_iter()
+                .map(|tag| tag.as_ref().to_string())
+                .collect(),
+            names: DashMap::new(),
+        };
+        if let Some(parent) = &self.scope {
+            scope.prefix.insert_str(0, &parent.prefix);
+            scope.tags.extend(parent.tags.iter().cloned());
+        }
+        MetricsRegistry {
+            scope: Some(Arc::new(scope)),
+            ..self.clone()
+        }
     }
-    fn get_delay(&self) -> std::time::Duration {
-        self.delay_time.unwrap_or_else(|| {
-            let delay_millis = get_env_or_fallback(DELAY_MILLIS_ENV_VAR, DEFAULT_DELAY_MILLIS);
-            std::time::Duration::from_millis(delay_millis)
-        })
+    fn scoped_key(&self, metric_key: MetricKey) -> MetricKey {
+        let Some(scope) = &self.scope else {
+            return metric_key;
+        };
+        let name = scope.prefixed(metric_key.get_name());
+        let mut tags = scope.tags.clone();
+        tags.extend(metric_key.get_tags().iter().cloned());
+        MetricKey::new(name, tags)
     }
-    fn get_observation_period(&self) -> std::time::Duration {
-        self.observation_period.unwrap_or_else(|| {
-            let observation_millis = get_env_or_fallback(
-                OBSERVATION_PERIOD_MILLIS_ENV_VAR,
-                DEFAULT_OBSERVATION_PERIOD_MILLIS,
-            );
-            std::time::Duration::from_millis(observation_millis)
-        })
-    }
-    /// We have to clone the bucket and let the user keep `self` so
-    /// that they can register new metrics
-    fn start(&self) -> std::thread::JoinHandle<()> {
-        let delay_duration = self.get_delay();
-        let wait_duration = self.get_observation_period();
-        let self_clone = self.clone();
-        std::thread::spawn(move || {
-            std::thread::sleep(delay_duration);
-            loop {
-                let start = Instant::now();
-                let client = self_clone.get_client();
-                self_clone.reset_and_emit(client);
-                let runtime = start.elapsed();
-                if let Some(remaining) = wait_duration.checked_sub(runtime) {
-                    std::thread::sleep(remaining);
-                }
-            }
-        })
+    fn get_sink(&self) -> &dyn Sink {
+        match &self.sink {
+            Some(sink) => sink.as_ref(),
+            None => sync_client(),
+        }
+    }
+    /// Moves emission onto an async task on `runtime` driven by `tokio::time::interval`, instead of
+    /// the registry's own thread, which is shut down first with a final flush. The task stops when
+    /// the runtime shuts down, call [MetricsRegistry::shutdown_async] beforehand to flush the last window.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_on(&self, runtime: &tokio::runtime::Handle) -> Result<(), ShutdownError> {
+        self.shutdown(DEFAULT_SHUTDOWN_TIMEOUT)?;
+        self.emitter
+            .install(|| emitter::spawn_task(self.clone(), runtime));
+        Ok(())
+    }
+    /// Signals the emitter to stop, waits up to `timeout` for it to perform a final
+    /// emission and joins it. Shutting down an already stopped registry is a no-op.
+    /// This blocks, from async code use [MetricsRegistry::shutdown_async] instead.
+    /// Instruments can still be used afterwards but are only emitted by [MetricsRegistry::flush].
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), ShutdownError> {
+        let mut state = self.emitter.lock();
+        let Some(handle) = state.handle.take() else {
+            return Ok(());
+        };
+        state.stopping = true;
+        self.emitter.notify_stop();
+        let (mut state, result) = self
+            .emitter
+            .wakeup
+            .wait_timeout_while(state, timeout, |state| !state.stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        if result.timed_out() {
+            // Leave the handle so a later shutdown can try again
+            state.handle = Some(handle);
+            return Err(ShutdownError::Timeout(timeout));
+        }
+        drop(state);
+        handle.join()
+    }
+    /// [MetricsRegistry::shutdown] without blocking the runtime while waiting for the final flush.
+    #[cfg(feature = "tokio")]
+    pub async fn shutdown_async(&self, timeout: Duration) -> Result<(), ShutdownError> {
+        let registry = self.clone();
+        tokio::task::spawn_blocking(move || registry.shutdown(timeout))
+            .await
+            .map_err(|_| ShutdownError::Panicked)?
+    }
+    /// [MetricsRegistry::shutdown] with a default timeout of 5 seconds.
+    pub fn flush_and_stop(&self) -> Result<(), ShutdownError> {
+        self.shutdown(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+    /// Returns a guard that shuts the registry down when dropped, e.g. at the end of `main`
+    /// in short-lived jobs so their last window isn't lost.
+    pub fn shutdown_guard(&self, timeout: Duration) -> ShutdownGuard {
+        ShutdownGuard {
+            registry: self.clone(),
+            timeout,
+        }
     }
     /// [register_metric]() has get_or_insert semantics.
     pub fn register_metric<M, T: MetricType::Impl + MakeInstrument>(
@@ -169,19 +577,174 @@ impl MetricsRegistry {
     {
         let metric: Metric<T> = metric.into();
         let instrument = metric.make_instrument();
-        let metric_key: MetricKey = metric.into();
+        self.register_instrument(metric, instrument)
+    }
+    /// Inserts `instrument` for `metric` unless the metric is already registered, in which
+    /// case the existing instrument is returned. Past the registry's series limits the
+    /// metric's `overflow:true` series is returned instead.
+    pub(crate) fn register_instrument<T: MetricType::Impl + MakeInstrument>(
+        &self,
+        metric: Metric<T>,
+        instrument: <T as MakeInstrument>::InstrumentType,
+    ) -> Result<<T as MakeInstrument>::InstrumentType, MetricRegistrationError>
+    where
+        <T as MakeInstrument>::InstrumentType: Into<Instrument> + Clone + 'static,
+    {
+        self.admit_instrument(metric, instrument)
+            .map(|(instrument, _)| instrument)
+    }
+    /// [MetricsRegistry::register_instrument], also returning whether `metric` got its own
+    /// series rather than the `overflow:true` one.
+    pub(crate) fn admit_instrument<T: MetricType::Impl + MakeInstrument>(
+        &self,
+        metric: Metric<T>,
+        instrument: <T as MakeInstrument>::InstrumentType,
+    ) -> Result<(<T as MakeInstrument>::InstrumentType, bool), MetricRegistrationError>
+    where
+        <T as MakeInstrument>::InstrumentType: Into<Instrument> + Clone + 'static,
+    {
+        let metric_key = self.scoped_key(metric.into());
+        let name = metric_key.get_name();
         let entry = self.metrics.entry(metric_key);
         match entry {
             dashmap::mapref::entry::Entry::Occupied(ref occupied) => {
                 let instrument_enum = occupied.get().to_owned();
-                instrument_enum.downcast::<T>()
+                return instrument_enum
+                    .downcast::<T>()
+                    .map(|instrument| (instrument, true));
             }
             dashmap::mapref::entry::Entry::Vacant(vacant) => {
-                let instrument_enum: Instrument = instrument.clone().into();
-                vacant.insert(instrument_enum);
-                Ok(instrument)
+                if let Some(evicted) = self.take_evicted::<T>(vacant.key()) {
+                    let instrument_enum: Instrument = evicted.clone().into();
+                    instrument_enum.liveness().revive();
+                    if let Some(binding) = instrument_enum.liveness().binding() {
+                        binding.readmit();
+                    }
+                    vacant.insert(instrument_enum);
+                    return Ok((evicted, true));
+                }
+                if self.series.try_admit(name, self.limits) {
+                    let instrument_enum: Instrument = instrument.clone().into();
+                    instrument_enum
+                        .liveness()
+                        .bind(self.binding(vacant.key().clone(), true));
+                    vacant.insert(instrument_enum);
+                    return Ok((instrument, true));
+                }
             }
         }
+        trace!("Series limit reached for {name}, redirecting to {OVERFLOW_TAG}");
+        self.get_or_insert_uncounted(
+            MetricKey::new(REJECTED_SERIES_METRIC, btreeset! {format!("metric:{name}")}),
+            || Instrument::count().into(),
+        )
+        .downcast::<MetricType::Count>()?
+        .increment();
+        self.get_or_insert_uncounted(
+            MetricKey::new(name, btreeset! {OVERFLOW_TAG.to_string()}),
+            || instrument.into(),
+        )
+        .downcast::<T>()
+        .map(|instrument| (instrument, false))
+    }
+    /// Bypasses the series limits, for series that are bounded by the number of stat names.
+    fn get_or_insert_uncounted<F: FnOnce() -> Instrument>(
+        &self,
+        metric_key: MetricKey,
+        make_instrument: F,
+    ) -> Instrument {
+        let binding = self.binding(metric_key.clone(), false);
+        self.metrics
+            .entry(metric_key)
+            .or_insert_with(|| {
+                let instrument = make_instrument();
+                instrument.liveness().bind(binding);
+                instrument
+            })
+            .value()
+            .clone()
+    }
+    /// The instrument evicted for `metric_key` if its handles are still held and it's a `T`.
+    fn take_evicted<T: MetricType::Impl + MakeInstrument>(
+        &self,
+        metric_key: &MetricKey,
+    ) -> Option<<T as MakeInstrument>::InstrumentType>
+    where
+        <T as MakeInstrument>::InstrumentType: Into<Instrument> + Clone + 'static,
+    {
+        let (_, instrument) = self.evicted.remove(metric_key)?;
+        instrument.downcast::<T>().ok()
+    }
+    /// Releases an instrument that was removed from the registry, keeping it around for
+    /// [MetricsRegistry::take_evicted] while it has handles.
+    fn retire(&self, metric_key: &MetricKey, instrument: &Instrument) {
+        let liveness = instrument.liveness();
+        if let Some(binding) = liveness.binding() {
+            binding.release();
+        }
+        if Arc::strong_count(liveness) > 1 {
+            self.evicted.insert(metric_key.clone(), instrument.clone());
+        }
+    }
+    fn binding(&self, key: MetricKey, counted: bool) -> SeriesBinding {
+        SeriesBinding {
+            key,
+            counted,
+            generation: self.generation.clone(),
+            metrics: Arc::downgrade(&self.metrics),
+            evicted: Arc::downgrade(&self.evicted),
+            series: Arc::downgrade(&self.series),
+        }
+    }
+    /// Removes the series registered for `metric`, returns whether there was one. Its current
+    /// window stays with the instrument: handles that are still held put the series back, window
+    /// included, the next time they're updated or when `metric` is registered again. Without
+    /// handles the window is dropped.
+    pub fn unregister<T, M>(&self, metric: M) -> bool
+    where
+        T: MetricType::Impl,
+        M: Into<Metric<T>>,
+    {
+        let metric_key = self.scoped_key(metric.into().into());
+        self.metrics
+            .remove_if(&metric_key, |metric_key, instrument| {
+                instrument.liveness().evict();
+                self.retire(metric_key, instrument);
+                true
+            })
+            .is_some()
+    }
+    /// Overrides the registry's [EmissionPolicy] for the series registered for `metric`,
+    /// returns whether there was one.
+    pub fn set_emission_policy<T, M>(&self, metric: M, policy: EmissionPolicy) -> bool
+    where
+        T: MetricType::Impl,
+        M: Into<Metric<T>>,
+    {
+        let metric_key = self.scoped_key(metric.into().into());
+        match self.metrics.get(&metric_key) {
+            Some(instrument) => {
+                instrument.liveness().set_policy(policy);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Removes series that weren't updated in the last `idle_expiry` cycles, up to and including `generation`.
+    fn evict_idle(&self, generation: u64) {
+        let Some(idle_expiry) = self.idle_expiry else {
+            return;
+        };
+        let Some(idle_since) = generation.checked_sub(u64::from(idle_expiry.get())) else {
+            return;
+        };
+        self.metrics.retain(|metric_key, instrument| {
+            if !instrument.liveness().try_evict(idle_since) {
+                return true;
+            }
+            self.retire(metric_key, instrument);
+            false
+        });
     }
     /// [register_count]() has get_or_insert semantics.
     pub fn register_count<M>(&self, metric: M) -> Result<Count, MetricRegistrationError>
@@ -197,6 +760,25 @@ impl MetricsRegistry {
     {
         self.register_metric(metric)
     }
+    /// [register_gauge_with_aggregation]() has get_or_insert semantics, registering an existing
+    /// gauge with a different [GaugeAggregation] is an error.
+    pub fn register_gauge_with_aggregation<M>(
+        &self,
+        metric: M,
+        aggregation: GaugeAggregation,
+    ) -> Result<Gauge, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::Gauge>>,
+    {
+        let gauge = self.register_instrument(metric.into(), Gauge::new(aggregation))?;
+        if gauge.aggregation() != aggregation {
+            return Err(MetricRegistrationError::AggregationMismatch(
+                aggregation,
+                gauge.aggregation(),
+            ));
+        }
+        Ok(gauge)
+    }
     /// [register_timing_count]() has get_or_insert semantics.
     pub fn register_timing_count<M>(
         &self,
@@ -207,34 +789,140 @@ impl MetricsRegistry {
     {
         self.register_metric(metric)
     }
-    pub(crate) fn reset_and_emit(&self, client: &GnortClient) {
-        let clock = DefaultClock::default();
-        let before_emit = Instant::now();
+    /// Children of the family are registered on first use, see [Family::with_values](crate::family::Family::with_values).
+    pub fn register_count_family<M, I, S>(&self, metric: M, tag_keys: I) -> CountFamily
+    where
+        M: Into<Metric<MetricType::Count>>,
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        CountFamily::register(self, metric, tag_keys)
+    }
+    /// Children of the family are registered on first use, see [Family::with_values](crate::family::Family::with_values).
+    pub fn register_gauge_family<M, I, S>(&self, metric: M, tag_keys: I) -> GaugeFamily
+    where
+        M: Into<Metric<MetricType::Gauge>>,
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        GaugeFamily::register(self, metric, tag_keys)
+    }
+    /// Children of the family are registered on first use, see [Family::with_values](crate::family::Family::with_values).
+    pub fn register_timing_count_family<M, I, S>(&self, metric: M, tag_keys: I) -> TimingCountFamily
+    where
+        M: Into<Metric<MetricType::TimingCount>>,
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        TimingCountFamily::register(self, metric, tag_keys)
+    }
+    /// [register_outcome_timing_count]() has get_or_insert semantics for the success and failure series.
+    pub fn register_outcome_timing_count<M, E>(
+        &self,
+        metric: M,
+    ) -> Result<OutcomeTimingCount<E>, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::TimingCount>>,
+    {
+        OutcomeTimingCount::register(self, metric)
+    }
+    /// [register_histogram]() has get_or_insert semantics.
+    pub fn register_histogram<M>(&self, metric: M) -> Result<Histogram, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::Histogram>>,
+    {
+        self.register_metric(metric)
+    }
+    /// [register_distribution]() has get_or_insert semantics.
+    pub fn register_distribution<M>(
+        &self,
+        metric: M,
+    ) -> Result<Distribution, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::Distribution>>,
+    {
+        self.register_metric(metric)
+    }
+    /// [register_top_k]() has get_or_insert semantics.
+    pub fn register_top_k<M>(&self, metric: M) -> Result<TopK, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::TopK>>,
+    {
+        self.register_metric(metric)
+    }
+    /// [register_set]() has get_or_insert semantics.
+    pub fn register_set<M>(&self, metric: M) -> Result<Set, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::Set>>,
+    {
+        self.register_metric(metric)
+    }
+    /// [register_up_down_counter]() has get_or_insert semantics.
+    pub fn register_up_down_counter<M>(
+        &self,
+        metric: M,
+    ) -> Result<UpDownCounter, MetricRegistrationError>
+    where
+        M: Into<Metric<MetricType::UpDownCounter>>,
+    {
+        self.register_metric(metric)
+    }
+    /// Runs one emission cycle right away on the calling thread, resetting every instrument and
+    /// emitting to the registry's sink.
+    pub fn flush(&self) {
+        self.reset_and_emit(self.get_sink())
+    }
+    fn lock_next_emission(&self) -> std::sync::MutexGuard<'_, Duration> {
+        self.next_emission
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Runs an emission cycle if the registry's clock says one is due: at the end of every
+    /// observation window, see [WindowAlignment]. Returns whether it emitted. Intended for
+    /// [EmissionMode::Manual] registries polled from your own scheduler, windows that were
+    /// missed entirely are skipped rather than emitted back to back.
+    pub fn flush_if_due(&self) -> bool {
+        {
+            let mut next_emission = self.lock_next_emission();
+            if !self.schedule.is_due(*next_emission) {
+                return false;
+            }
+            *next_emission = self.schedule.next(*next_emission);
+        }
+        self.flush();
+        true
+    }
+    pub(crate) fn reset_and_emit(&self, sink: &dyn Sink) {
+        let clock = self.clock.as_ref();
+        let before_emit = clock.now();
+        // Updates from here on belong to the next window
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
         for ref_multi in self.metrics.iter() {
             let (metric, instrument) = ref_multi.pair();
-            check_and_wait(&clock, &self.rate_limiter, true);
+            check_and_wait(clock, &self.rate_limiter, true);
             let _ = instrument
-                .emit(client, metric)
+                .emit(sink, metric, &self.emission_policy, generation)
                 .map_err(|err| debug!("Got error emitting Datadog metric, was: {err}"));
         }
-        let after_emit = Instant::now();
-        let emission_micros = after_emit.duration_since(before_emit).as_micros();
-        let tags: &[&str] = &[];
-        let _ = client
-            .gauge(
-                TIME_TO_EMIT_METRICS,
-                (emission_micros as i64).to_string(),
-                tags,
-            )
+        self.evict_idle(generation);
+        // Evicted instruments can't be updated again once their last handle is dropped
+        self.evicted
+            .retain(|_, instrument| Arc::strong_count(instrument.liveness()) > 1);
+        let emission_micros = clock.now().saturating_sub(before_emit).as_micros();
+        let _ = sink
+            .gauge(TIME_TO_EMIT_METRICS, emission_micros as f64, &[])
             .map_err(|err| debug!("Got error emitting Datadog metric, was: {err}"));
+        let _ = sink
+            .flush()
+            .map_err(|err| debug!("Got error flushing Datadog metrics, was: {err}"));
     }
 }
 
-fn check_and_sleep(
-    clock: &DefaultClock,
-    rate_limiter: &DefaultDirectRateLimiter,
-    sleep: bool,
-) -> bool {
+fn new_rate_limiter(quota: Quota, clock: Arc<dyn Clock>) -> RegistryRateLimiter {
+    RateLimiter::direct_with_clock(quota, GovernorClock(clock))
+}
+
+fn check_and_sleep(clock: &dyn Clock, rate_limiter: &RegistryRateLimiter, sleep: bool) -> bool {
     let governed = rate_limiter.check();
     match governed {
         Ok(_) => {
@@ -247,8 +935,7 @@ fn check_and_sleep(
                 negative = negative
             );
             if sleep {
-                let wait_time = negative.wait_time_from(clock.now()).as_millis();
-                std::thread::sleep(Duration::from_millis(wait_time as u64));
+                clock.sleep(negative.wait_time_from(clock.now()));
             }
             // Try again
             false
@@ -256,7 +943,7 @@ fn check_and_sleep(
     }
 }
 
-fn check_and_wait(clock: &DefaultClock, rate_limiter: &DefaultDirectRateLimiter, sleep: bool) {
+fn check_and_wait(clock: &dyn Clock, rate_limiter: &RegistryRateLimiter, sleep: bool) {
     loop {
         let go_ahead = check_and_sleep(clock, rate_limiter, sleep);
         if go_ahead {
@@ -269,17 +956,436 @@ fn check_and_wait(clock: &DefaultClock, rate_limiter: &DefaultDirectRateLimiter,
 
 #[cfg(test)]
 mod test {
-    use std::sync::atomic::AtomicUsize;
+    use std::{sync::atomic::AtomicUsize, time::Instant};
 
     use super::*;
     use approx::*;
     use governor::{
-        clock::{self, Clock, FakeRelativeClock, QuantaInstant, Reference},
+        clock::{self, Clock, FakeRelativeClock, Reference},
         middleware,
         nanos::Nanos,
         state, Quota, RateLimiter,
     };
 
+    #[test]
+    fn test_custom_sink() {
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let registry = MetricsRegistry::new(RegistryConfig::default().with_sink(sink.clone()));
+        let count = registry
+            .register_count(Metric::from("gnort.test.sink.count").with_tags(["tag:x"]))
+            .unwrap();
+        count.fetch_add(3);
+        registry.flush();
+        sink.assert_count("gnort.test.sink.count", &["tag:x"], 3);
+        assert!(sink.last_gauge(TIME_TO_EMIT_METRICS, &[]).is_some());
+    }
+
+    fn long_lived_registry() -> (MetricsRegistry, Arc<crate::testing::RecordingSink>) {
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let config = RegistryConfig {
+            observation_period: Some(Duration::from_secs(3600)),
+            delay_time: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        (MetricsRegistry::new(config.with_sink(sink.clone())), sink)
+    }
+
+    #[test]
+    fn test_shutdown_flushes_last_window() {
+        let (registry, sink) = long_lived_registry();
+        let count = registry
+            .register_count("gnort.test.shutdown.count")
+            .unwrap();
+        count.fetch_add(7);
+        let start = Instant::now();
+        registry.shutdown(Duration::from_secs(5)).unwrap();
+        // Shutdown interrupts the emitter's sleep rather than waiting it out
+        assert!(start.elapsed() < Duration::from_secs(5));
+        sink.assert_count("gnort.test.shutdown.count", &[], 7);
+        // Already stopped, nothing else is emitted
+        count.increment();
+        registry.flush_and_stop().unwrap();
+        sink.assert_count("gnort.test.shutdown.count", &[], 7);
+    }
+
+    #[test]
+    fn test_shutdown_guard() {
+        let (registry, sink) = long_lived_registry();
+        let count = registry
+            .register_count("gnort.test.shutdown.count")
+            .unwrap();
+        {
+            let _guard = registry.shutdown_guard(Duration::from_secs(5));
+            count.increment();
+        }
+        sink.assert_count("gnort.test.shutdown.count", &[], 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spawn_on() {
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let config = RegistryConfig {
+            observation_period: Some(Duration::from_millis(20)),
+            delay_time: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let registry = MetricsRegistry::new(config.with_sink(sink.clone()));
+        registry
+            .spawn_on(&tokio::runtime::Handle::current())
+            .unwrap();
+        let count = registry.register_count("gnort.test.task.count").unwrap();
+        count.fetch_add(2);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sink.count_total("gnort.test.task.count", &[]) != Some(2) && Instant::now() < deadline
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        sink.assert_count("gnort.test.task.count", &[], 2);
+        count.increment();
+        registry
+            .shutdown_async(Duration::from_secs(5))
+            .await
+            .unwrap();
+        sink.assert_count("gnort.test.task.count", &[], 3);
+    }
+
+    #[test]
+    fn test_manual_emission_with_fake_clock() {
+        let clock = crate::clock::FakeClock::default();
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let config = RegistryConfig {
+            observation_period: Some(Duration::from_secs(10)),
+            delay_time: Some(Duration::from_secs(5)),
+            rate_limit_per_second: Some(nonzero!(1u32)),
+            burst_limit: Some(nonzero!(1u32)),
+            ..Default::default()
+        };
+        let registry = MetricsRegistry::new(
+            config
+                .with_sink(sink.clone())
+                .with_emission(EmissionMode::Manual)
+                .with_clock(Arc::new(clock.clone())),
+        );
+        let counts: Vec<Count> = ["a", "b", "c"]
+            .into_iter()
+            .map(|tag| {
+                registry
+                    .register_count(Metric::from("gnort.test.manual.count").with_tags([tag]))
+                    .unwrap()
+            })
+            .collect();
+        counts.iter().for_each(|count| {
+            count.increment();
+        });
+        assert!(!registry.flush_if_due());
+        clock.advance(Duration::from_secs(5));
+        assert!(registry.flush_if_due());
+        sink.assert_count("gnort.test.manual.count", &["a"], 1);
+        // Rate limited to one metric per second, the fake clock moves instead of sleeping
+        assert!(crate::clock::Clock::now(&clock) >= Duration::from_secs(7));
+        assert!(!registry.flush_if_due());
+        // Missed windows are skipped rather than emitted back to back
+        clock.advance(Duration::from_secs(25));
+        assert!(registry.flush_if_due());
+        assert!(!registry.flush_if_due());
+        counts[0].increment();
+        registry.flush();
+        sink.assert_count("gnort.test.manual.count", &["a"], 2);
+    }
+
+    #[test]
+    fn test_epoch_aligned_windows() {
+        let clock = crate::clock::FakeClock::default();
+        clock.advance(Duration::from_secs(7));
+        let config = RegistryConfig {
+            observation_period: Some(Duration::from_secs(10)),
+            delay_time: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let registry = MetricsRegistry::new(
+            config
+                .with_sink(Arc::new(crate::testing::RecordingSink::default()))
+                .with_emission(EmissionMode::Manual)
+                .with_alignment(WindowAlignment::Epoch {
+                    offset: Duration::from_secs(12),
+                })
+                .with_clock(Arc::new(clock.clone())),
+        );
+        // Windows end at 12s, 22s, 32s.. since the epoch regardless of the delay
+        clock.advance(Duration::from_secs(4));
+        assert!(!registry.flush_if_due());
+        clock.advance(Duration::from_secs(1));
+        assert!(registry.flush_if_due());
+        assert!(!registry.flush_if_due());
+        // Late polls don't shift later windows
+        clock.advance(Duration::from_secs(13));
+        assert!(registry.flush_if_due());
+        clock.advance(Duration::from_secs(6));
+        assert!(!registry.flush_if_due());
+        clock.advance(Duration::from_secs(1));
+        assert!(registry.flush_if_due());
+    }
+
+    #[test]
+    fn test_host_jitter() {
+        let max_jitter = Duration::from_secs(2);
+        let alignment = WindowAlignment::epoch_with_host_jitter(max_jitter);
+        assert_eq!(
+            alignment,
+            WindowAlignment::epoch_with_host_jitter(max_jitter)
+        );
+        match alignment {
+            WindowAlignment::Epoch { offset } => assert!(offset < max_jitter),
+            WindowAlignment::Relative => panic!("expected epoch alignment"),
+        }
+    }
+
+    #[test]
+    fn test_series_limits() {
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_sink(sink.clone())
+                .with_emission(EmissionMode::Manual)
+                .with_max_series_per_metric(2)
+                .with_max_series(3),
+        );
+        let register = |name: &'static str, request_id: &str| {
+            registry
+                .register_count(Metric::from(name).with_tags([format!("request_id:{request_id}")]))
+                .unwrap()
+        };
+        register("gnort.test.requests", "1").increment();
+        register("gnort.test.requests", "2").increment();
+        // Past the per-metric limit
+        register("gnort.test.requests", "3").increment();
+        register("gnort.test.requests", "4").increment();
+        // Already registered series are still returned
+        register("gnort.test.requests", "1").increment();
+        register("gnort.test.errors", "1").increment();
+        // Past the global limit
+        register("gnort.test.errors", "2").increment();
+        registry.flush();
+        sink.assert_count("gnort.test.requests", &["request_id:1"], 2);
+        sink.assert_count("gnort.test.requests", &["request_id:2"], 1);
+        sink.assert_not_emitted("gnort.test.requests", &["request_id:3"]);
+        sink.assert_count("gnort.test.requests", &[OVERFLOW_TAG], 2);
+        sink.assert_count("gnort.test.errors", &["request_id:1"], 1);
+        sink.assert_count("gnort.test.errors", &[OVERFLOW_TAG], 1);
+        sink.assert_count(REJECTED_SERIES_METRIC, &["metric:gnort.test.requests"], 2);
+        sink.assert_count(REJECTED_SERIES_METRIC, &["metric:gnort.test.errors"], 1);
+    }
+
+    fn manual_registry(
+        config: RegistryConfig,
+    ) -> (MetricsRegistry, Arc<crate::testing::RecordingSink>) {
+        let sink = Arc::new(crate::testing::RecordingSink::default());
+        let registry = MetricsRegistry::new(
+            config
+                .with_sink(sink.clone())
+                .with_emission(EmissionMode::Manual),
+        );
+        (registry, sink)
+    }
+
+    #[test]
+    fn test_idle_expiry() {
+        let (registry, sink) = manual_registry(
+            RegistryConfig::default()
+                .with_idle_expiry(nonzero!(2u32))
+                .with_max_series(2),
+        );
+        let idle = registry.register_count("gnort.test.idle").unwrap();
+        let busy = registry.register_count("gnort.test.busy").unwrap();
+        idle.increment();
+        for _ in 0..3 {
+            busy.increment();
+            registry.flush();
+        }
+        // Not updated in the last two windows
+        assert_eq!(registry.metrics.len(), 1);
+        sink.clear();
+        registry.flush();
+        sink.assert_not_emitted("gnort.test.idle", &[]);
+        sink.assert_count("gnort.test.busy", &[], 0);
+        // Evicted series don't count against the series limit
+        let other = registry.register_count("gnort.test.other").unwrap();
+        other.increment();
+        // The held handle puts the series back
+        idle.increment();
+        registry.flush();
+        sink.assert_count("gnort.test.idle", &[], 1);
+        sink.assert_count("gnort.test.other", &[], 1);
+    }
+
+    #[test]
+    fn test_unregister() {
+        let (registry, sink) = manual_registry(RegistryConfig::default());
+        let count = registry.register_count("gnort.test.unregister").unwrap();
+        count.increment();
+        assert!(registry.unregister::<MetricType::Count, _>("gnort.test.unregister"));
+        assert!(!registry.unregister::<MetricType::Count, _>("gnort.test.unregister"));
+        registry.flush();
+        sink.assert_not_emitted("gnort.test.unregister", &[]);
+        count.increment();
+        registry.flush();
+        // The window pending when it was unregistered is kept by the handle
+        sink.assert_count("gnort.test.unregister", &[], 2);
+    }
+
+    #[test]
+    fn test_reregister_evicted() {
+        let (registry, sink) =
+            manual_registry(RegistryConfig::default().with_idle_expiry(nonzero!(1u32)));
+        let old = registry.register_count("gnort.test.evicted").unwrap();
+        old.increment();
+        registry.flush();
+        registry.flush();
+        assert!(registry.metrics.is_empty());
+        // Registering again shares the instrument with the handle that's still held
+        let new = registry.register_count("gnort.test.evicted").unwrap();
+        old.increment();
+        new.increment();
+        sink.clear();
+        registry.flush();
+        sink.assert_count("gnort.test.evicted", &[], 2);
+        // Same after unregistering
+        old.increment();
+        assert!(registry.unregister::<MetricType::Count, _>("gnort.test.evicted"));
+        registry.register_count("gnort.test.evicted").unwrap();
+        sink.clear();
+        registry.flush();
+        sink.assert_count("gnort.test.evicted", &[], 1);
+        // Without handles nothing is kept around
+        drop((old, new));
+        registry.flush();
+        registry.flush();
+        assert!(registry.metrics.is_empty());
+        assert!(registry.evicted.is_empty());
+    }
+
+    #[test]
+    fn test_emission_policy() {
+        let (registry, sink) = manual_registry(
+            RegistryConfig::default().with_emission_policy(
+                EmissionPolicy::default()
+                    .with_skip_zero_counts(true)
+                    .with_skip_unchanged_gauges(true)
+                    .with_heartbeat(nonzero!(3u32)),
+            ),
+        );
+        let count = registry.register_count("gnort.test.count").unwrap();
+        let gauge = registry.register_gauge("gnort.test.gauge").unwrap();
+        registry.register_count("gnort.test.always").unwrap();
+        assert!(registry.set_emission_policy(
+            Metric::<MetricType::Count>::from("gnort.test.always"),
+            EmissionPolicy::default(),
+        ));
+        count.increment();
+        gauge.swap(1.0);
+        registry.flush();
+        sink.assert_count("gnort.test.count", &[], 1);
+        sink.assert_gauge("gnort.test.gauge", &[], 1.0);
+        for _ in 0..2 {
+            sink.clear();
+            registry.flush();
+            sink.assert_not_emitted("gnort.test.count", &[]);
+            sink.assert_not_emitted("gnort.test.gauge", &[]);
+            sink.assert_count("gnort.test.always", &[], 0);
+        }
+        // Three windows after they were last emitted
+        sink.clear();
+        registry.flush();
+        sink.assert_count("gnort.test.count", &[], 0);
+        sink.assert_gauge("gnort.test.gauge", &[], 1.0);
+        sink.clear();
+        gauge.swap(1.0);
+        registry.flush();
+        // Written, even though the value didn't change
+        sink.assert_gauge("gnort.test.gauge", &[], 1.0);
+        sink.assert_not_emitted("gnort.test.count", &[]);
+    }
+
+    #[test]
+    fn test_scoped() {
+        let (registry, sink) = manual_registry(RegistryConfig::default());
+        let payments = registry.scoped("payments.", ["component:payments"]);
+        let stripe = payments.scoped("stripe.", ["provider:stripe"]);
+        payments
+            .register_count(Metric::from("requests").with_tags(["route:/charge"]))
+            .unwrap()
+            .increment();
+        stripe
+            .register_timing_count("latency")
+            .unwrap()
+            .add_timing(&Duration::from_millis(5));
+        stripe
+            .register_count_family("errors", ["code"])
+            .with_values(&["card_declined"])
+            .unwrap()
+            .increment();
+        // The parent shares the scope's series
+        registry
+            .register_count(
+                Metric::from("payments.requests")
+                    .with_tags(["component:payments", "route:/charge"]),
+            )
+            .unwrap()
+            .increment();
+        registry.flush();
+        sink.assert_count(
+            "payments.requests",
+            &["component:payments", "route:/charge"],
+            2,
+        );
+        let stripe_tags = ["component:payments", "provider:stripe"];
+        sink.assert_count("payments.stripe.latency", &stripe_tags, 1);
+        sink.assert_count(
+            "payments.stripe.errors",
+            &[
+                "code:card_declined",
+                "component:payments",
+                "provider:stripe",
+            ],
+            1,
+        );
+        assert!(stripe.unregister::<MetricType::TimingCount, _>("latency"));
+        // Prefixed names are cached per scope
+        let scope = stripe.scope.as_ref().unwrap();
+        assert_eq!(scope.names.len(), 2);
+        assert!(std::ptr::eq(
+            scope.prefixed("latency"),
+            scope.prefixed("latency")
+        ));
+    }
+
+    #[test]
+    fn test_register_gauge_with_aggregation() {
+        let registry = MetricsRegistry::new(RegistryConfig::default());
+        let max_gauge = registry
+            .register_gauge_with_aggregation("gnort.test.queue.max", GaugeAggregation::Max)
+            .unwrap();
+        let same_gauge = registry
+            .register_gauge_with_aggregation("gnort.test.queue.max", GaugeAggregation::Max)
+            .unwrap();
+        max_gauge.record(5.0);
+        same_gauge.record(3.0);
+        assert_eq!(max_gauge.load(), 5.0);
+        // Plain registration returns the gauge with the aggregation it was registered with
+        assert!(matches!(
+            registry.register_gauge("gnort.test.queue.max"),
+            Ok(gauge) if gauge.aggregation() == GaugeAggregation::Max
+        ));
+        assert!(matches!(
+            registry.register_gauge_with_aggregation("gnort.test.queue.max", GaugeAggregation::Sum),
+            Err(MetricRegistrationError::AggregationMismatch(
+                GaugeAggregation::Sum,
+                GaugeAggregation::Max
+            ))
+        ));
+    }
+
     #[test]
     fn test_approx() {
         assert!(!relative_eq!(1.0f64, 0.8f64, max_relative = 0.1));
@@ -323,17 +1429,17 @@ mod test {
 
     fn slam_rate_limiter_real(
         counter: Counter,
-        clock: DefaultClock,
-        rl: &DefaultDirectRateLimiter,
-        start: QuantaInstant,
-        time_limit: Nanos,
+        clock: Arc<dyn crate::clock::Clock>,
+        rl: &RegistryRateLimiter,
+        start: Duration,
+        time_limit: Duration,
         sleep: bool,
     ) {
         loop {
-            if clock.now().duration_since(start) > time_limit {
+            if clock.now().saturating_sub(start) > time_limit {
                 break;
             }
-            check_and_wait(&clock, &rl, sleep);
+            check_and_wait(clock.as_ref(), rl, sleep);
             let _ = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         }
     }
@@ -345,9 +1451,9 @@ mod test {
         (Arc::new(rl), clock)
     }
 
-    fn test_rate_limiter_real() -> Arc<DefaultDirectRateLimiter> {
+    fn test_rate_limiter_real(clock: Arc<dyn crate::clock::Clock>) -> Arc<RegistryRateLimiter> {
         let q = Quota::per_second(DEFAULT_RATE_LIMIT_PER_SECOND).allow_burst(DEFAULT_BURST_LIMIT);
-        let rl = RateLimiter::direct(q);
+        let rl = new_rate_limiter(q, clock);
         Arc::new(rl)
     }
 
@@ -384,10 +1490,10 @@ mod test {
     // Release mode y'all.
     #[test]
     fn test_governor_real() {
-        let rl = test_rate_limiter_real();
+        let clock: Arc<dyn crate::clock::Clock> = Arc::new(SystemClock::default());
+        let rl = test_rate_limiter_real(clock.clone());
         let counter = Arc::new(AtomicUsize::new(0));
-        let time_limit = Duration::from_secs(1).into();
-        let clock = DefaultClock::default();
+        let time_limit = Duration::from_secs(1);
         let start = clock.now();
         slam_rate_limiter_real(counter.clone(), clock, &rl, start, time_limit, true);
         let rate_limit = DEFAULT_RATE_LIMIT_PER_SECOND.get() as f64;
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use crate::client::DogstatsdError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error(transparent)]
+    Dogstatsd(#[from] DogstatsdError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Sink error: {0}")]
+    Other(String),
+}
+
+pub type SinkResult = Result<(), SinkError>;
+
+// Most samples the default `Sink::weighted_distribution` expands a bin into per call
+const EXPANDED_CHUNK_SIZE: usize = 64;
+
+/// Destination for the aggregated values [MetricsRegistry](crate::MetricsRegistry) emits every
+/// observation window. [GnortClient](crate::GnortClient) is the DogStatsD implementation, implement
+/// this to send metrics to another backend or to capture them in tests.
+///
+/// `name` and `tags` are exactly as registered, any namespace or default tags are up to the sink.
+pub trait Sink: Send + Sync {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult;
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult;
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult;
+    fn set(&self, name: &str, value: &str, tags: &[&str]) -> SinkResult;
+    /// Every value is a separate sample of the distribution.
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult;
+    /// Pre-aggregated distribution where each `(value, count)` bin stands for `count` samples
+    /// of `value`. Defaults to expanding bins into [Sink::distribution] calls of at most 64
+    /// samples, the built-in sinks send each bin once with its weight instead.
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        for &(value, count) in bins {
+            let chunk = [value; EXPANDED_CHUNK_SIZE];
+            let mut remaining = count;
+            while remaining > 0 {
+                let size = remaining.min(EXPANDED_CHUNK_SIZE as u64);
+                self.distribution(name, &chunk[..size as usize], tags)?;
+                remaining -= size;
+            }
+        }
+        Ok(())
+    }
+    /// One window of a [TimingCount](crate::instrument::TimingCount), sent as a count of
+    /// `name.time` for the `sum` and a count of `name` for the `count` unless overridden.
+    fn timing_count(&self, name: &str, sum: i64, count: i64, tags: &[&str]) -> SinkResult {
+        self.count(&format!("{}.time", name), sum, tags)?;
+        self.count(name, count, tags)
+    }
+    /// Called at the end of every emission cycle so sinks that buffer can send what's left.
+    fn flush(&self) -> SinkResult {
+        Ok(())
+    }
+}
+
+/// [Sink] forwarding everything to each of its sinks in order, e.g. to keep sending to DogStatsD
+/// while also exporting to Prometheus. Every sink is called even if an earlier one fails, the
+/// first error is returned.
+#[derive(Clone, Default)]
+pub struct FanoutSink {
+    sinks: Vec<Arc<dyn Sink>>,
+}
+
+impl FanoutSink {
+    pub fn new<I: IntoIterator<Item = Arc<dyn Sink>>>(sinks: I) -> Self {
+        Self {
+            sinks: sinks.into_iter().collect(),
+        }
+    }
+    pub fn with_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+    fn each<F: Fn(&dyn Sink) -> SinkResult>(&self, f: F) -> SinkResult {
+        let mut result = Ok(());
+        for sink in &self.sinks {
+            let sent = f(sink.as_ref());
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+}
+
+impl Sink for FanoutSink {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.count(name, value, tags))
+    }
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.gauge(name, value, tags))
+    }
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.timing(name, milliseconds, tags))
+    }
+    fn set(&self, name: &str, value: &str, tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.set(name, value, tags))
+    }
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.distribution(name, values, tags))
+    }
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.weighted_distribution(name, bins, tags))
+    }
+    fn timing_count(&self, name: &str, sum: i64, count: i64, tags: &[&str]) -> SinkResult {
+        self.each(|sink| sink.timing_count(name, sum, count, tags))
+    }
+    fn flush(&self) -> SinkResult {
+        self.each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::RecordingSink;
+
+    struct FailingSink;
+
+    impl Sink for FailingSink {
+        fn count(&self, _name: &str, _value: i64, _tags: &[&str]) -> SinkResult {
+            Err(SinkError::Other("unavailable".to_string()))
+        }
+        fn gauge(&self, _name: &str, _value: f64, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn timing(&self, _name: &str, _milliseconds: i64, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn set(&self, _name: &str, _value: &str, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn distribution(&self, _name: &str, _values: &[f64], _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+    }
+
+    /// Only implements the required methods, recording the size of every distribution call.
+    #[derive(Default)]
+    struct ChunkSink(std::sync::Mutex<Vec<usize>>);
+
+    impl Sink for ChunkSink {
+        fn count(&self, _name: &str, _value: i64, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn gauge(&self, _name: &str, _value: f64, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn timing(&self, _name: &str, _milliseconds: i64, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn set(&self, _name: &str, _value: &str, _tags: &[&str]) -> SinkResult {
+            Ok(())
+        }
+        fn distribution(&self, _name: &str, values: &[f64], _tags: &[&str]) -> SinkResult {
+            self.0.lock().unwrap().push(values.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_default_weighted_distribution() {
+        let sink = ChunkSink::default();
+        sink.weighted_distribution("gnort.test.dist", &[(1.5, 2), (2.5, 150), (3.5, 0)], &[])
+            .unwrap();
+        assert_eq!(*sink.0.lock().unwrap(), vec![2, 64, 64, 22]);
+    }
+
+    #[test]
+    fn test_fanout() {
+        let (first, second) = (
+            Arc::new(RecordingSink::default()),
+            Arc::new(RecordingSink::default()),
+        );
+        let fanout = FanoutSink::new([Arc::new(FailingSink) as Arc<dyn Sink>])
+            .with_sink(first.clone())
+            .with_sink(second.clone());
+        // Later sinks still get the count after the first one fails
+        assert!(fanout
+            .count("gnort.test.count", 2, &["team:metrics"])
+            .is_err());
+        fanout.gauge("gnort.test.gauge", 1.5, &[]).unwrap();
+        for sink in [first, second] {
+            sink.assert_count("gnort.test.count", &["team:metrics"], 2);
+            sink.assert_gauge("gnort.test.gauge", &[], 1.5);
+        }
+    }
+}
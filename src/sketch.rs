@@ -0,0 +1,512 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+};
+
+use thiserror::Error;
+
+/// Default relative accuracy of [DDSketch], quantiles are within 1% of the true value.
+pub const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+/// Default bound on the number of bins per store before the lowest bins are collapsed.
+pub const DEFAULT_MAX_BINS: usize = 2048;
+// Magnitudes below this are counted as zero rather than indexed
+const MIN_INDEXABLE_VALUE: f64 = 1e-9;
+/// Default width of [CountMinSketch], overestimates are bounded by ~0.1% of the total count.
+pub const DEFAULT_CMS_WIDTH: usize = 2048;
+/// Default depth of [CountMinSketch], the error bound holds with ~99% probability.
+pub const DEFAULT_CMS_DEPTH: usize = 5;
+/// Default precision of [HyperLogLog], 2^12 registers for a standard error of ~1.6%.
+pub const DEFAULT_HLL_PRECISION: u8 = 12;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SketchError {
+    #[error("Can't merge sketches with different relative accuracies, expected: {0}, was: {1}")]
+    IncompatibleAccuracy(f64, f64),
+    #[error("Can't merge sketches with different dimensions, expected: {0:?}, was: {1:?}")]
+    IncompatibleDimensions((usize, usize), (usize, usize)),
+    #[error("Can't merge sketches with different precisions, expected: {0}, was: {1}")]
+    IncompatiblePrecision(u8, u8),
+}
+
+pub(crate) fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Relative-error quantile sketch after [DDSketch](https://arxiv.org/abs/1908.10693).
+/// Values are mapped to logarithmically sized bins so any quantile is reported within
+/// `relative_accuracy` of the true value. Sketches with the same accuracy merge losslessly,
+/// which makes them safe to combine across threads, registries or hosts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DDSketch {
+    relative_accuracy: f64,
+    gamma: f64,
+    ln_gamma: f64,
+    max_bins: usize,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        Self::new(DEFAULT_RELATIVE_ACCURACY)
+    }
+}
+
+impl DDSketch {
+    pub fn new(relative_accuracy: f64) -> Self {
+        let relative_accuracy = relative_accuracy.clamp(f64::EPSILON, 0.5);
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Self {
+            relative_accuracy,
+            gamma,
+            ln_gamma: gamma.ln(),
+            max_bins: DEFAULT_MAX_BINS,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+    pub fn with_max_bins(self, max_bins: usize) -> Self {
+        Self {
+            max_bins: max_bins.max(1),
+            ..self
+        }
+    }
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    fn key(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.ln_gamma).ceil() as i32
+    }
+    fn value(&self, key: i32) -> f64 {
+        2.0 * self.gamma.powi(key) / (self.gamma + 1.0)
+    }
+    fn collapse(store: &mut BTreeMap<i32, u64>, max_bins: usize) {
+        // Fold the lowest bins together, losing accuracy only for the smallest magnitudes
+        while store.len() > max_bins {
+            let (_, lowest) = store.pop_first().expect("store is non-empty");
+            let (_, next) = store
+                .iter_mut()
+                .next()
+                .expect("store has more than one bin");
+            *next += lowest;
+        }
+    }
+    pub fn add(&mut self, value: f64) {
+        self.add_with_count(value, 1)
+    }
+    pub fn add_with_count(&mut self, value: f64, count: u64) {
+        if value.is_nan() || count == 0 {
+            return;
+        }
+        if value.abs() < MIN_INDEXABLE_VALUE {
+            self.zero_count += count;
+        } else if value > 0.0 {
+            *self.positive.entry(self.key(value)).or_default() += count;
+            Self::collapse(&mut self.positive, self.max_bins);
+        } else {
+            *self.negative.entry(self.key(-value)).or_default() += count;
+            Self::collapse(&mut self.negative, self.max_bins);
+        }
+        self.count += count;
+        self.sum += value * count as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+    pub fn merge(&mut self, other: &DDSketch) -> Result<(), SketchError> {
+        if self.relative_accuracy != other.relative_accuracy {
+            return Err(SketchError::IncompatibleAccuracy(
+                self.relative_accuracy,
+                other.relative_accuracy,
+            ));
+        }
+        for (key, count) in &other.positive {
+            *self.positive.entry(*key).or_default() += count;
+        }
+        for (key, count) in &other.negative {
+            *self.negative.entry(*key).or_default() += count;
+        }
+        Self::collapse(&mut self.positive, self.max_bins);
+        Self::collapse(&mut self.negative, self.max_bins);
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        Ok(())
+    }
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    pub fn min(&self) -> Option<f64> {
+        (!self.is_empty()).then_some(self.min)
+    }
+    pub fn max(&self) -> Option<f64> {
+        (!self.is_empty()).then_some(self.max)
+    }
+    pub fn avg(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.sum / self.count as f64)
+    }
+    /// Estimated value at quantile `q` (0.0..=1.0), `None` if nothing was recorded.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * (self.count - 1) as f64;
+        let estimate = self
+            .bins()
+            .scan(0u64, |seen, (value, count)| {
+                *seen += count;
+                Some((value, *seen))
+            })
+            .find(|(_, seen)| *seen as f64 > rank)
+            .map(|(value, _)| value)
+            .unwrap_or(self.max);
+        Some(estimate.clamp(self.min, self.max))
+    }
+    /// Representative value and count of every non-empty bin, in ascending order of value.
+    pub fn bins(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let negative = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(key, count)| (-self.value(*key), *count));
+        let zero = (self.zero_count > 0).then_some((0.0, self.zero_count));
+        let positive = self
+            .positive
+            .iter()
+            .map(|(key, count)| (self.value(*key), *count));
+        negative.chain(zero).chain(positive)
+    }
+}
+
+/// Fixed-memory frequency sketch after [Cormode and Muthukrishnan](https://dsf.berkeley.edu/cs286/papers/countmin-latin2004.pdf).
+/// Estimates never undercount, and overcount by at most `e / width` of the total count
+/// with probability `1 - e^-depth`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+}
+
+impl Default for CountMinSketch {
+    fn default() -> Self {
+        Self::new(DEFAULT_CMS_WIDTH, DEFAULT_CMS_DEPTH)
+    }
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        let (width, depth) = (width.max(1), depth.max(1));
+        Self {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+        }
+    }
+    /// Sized so estimates overcount by at most `epsilon` of the total count with probability `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil() as usize;
+        Self::new(width, depth)
+    }
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.depth)
+    }
+    fn index<K: Hash + ?Sized>(&self, row: usize, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        row * self.width + (hasher.finish() % self.width as u64) as usize
+    }
+    /// Adds `count` observations of `key` and returns its updated estimate.
+    pub fn add<K: Hash + ?Sized>(&mut self, key: &K, count: u64) -> u64 {
+        (0..self.depth)
+            .map(|row| {
+                let index = self.index(row, key);
+                self.counters[index] += count;
+                self.counters[index]
+            })
+            .min()
+            .unwrap_or_default()
+    }
+    pub fn estimate<K: Hash + ?Sized>(&self, key: &K) -> u64 {
+        (0..self.depth)
+            .map(|row| self.counters[self.index(row, key)])
+            .min()
+            .unwrap_or_default()
+    }
+    pub fn merge(&mut self, other: &CountMinSketch) -> Result<(), SketchError> {
+        if self.dimensions() != other.dimensions() {
+            return Err(SketchError::IncompatibleDimensions(
+                self.dimensions(),
+                other.dimensions(),
+            ));
+        }
+        for (counter, other) in self.counters.iter_mut().zip(&other.counters) {
+            *counter += other;
+        }
+        Ok(())
+    }
+    pub fn clear(&mut self) {
+        self.counters.iter_mut().for_each(|counter| *counter = 0);
+    }
+}
+
+/// Cardinality sketch after [HyperLogLog](https://algo.inria.fr/flajolet/Publications/FlFuGaMe07.pdf).
+/// `2^precision` one-byte registers estimate the number of distinct values observed with a
+/// standard error of `1.04 / sqrt(2^precision)`. Sketches with the same precision merge losslessly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_HLL_PRECISION)
+    }
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u8) -> Self {
+        let precision = precision.clamp(4, 18);
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+    /// Builds a sketch out of raw registers, the number of registers must be a power of two.
+    pub(crate) fn from_registers(registers: Vec<u8>) -> Self {
+        debug_assert!(registers.len().is_power_of_two());
+        Self {
+            precision: registers.len().trailing_zeros() as u8,
+            registers,
+        }
+    }
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+    /// Register index and rank for a hashed value
+    pub(crate) fn register_for(precision: u8, hash: u64) -> (usize, u8) {
+        let index = (hash >> (64 - precision)) as usize;
+        // The sentinel bit caps the rank when the remaining bits are all zero
+        let remaining = (hash << precision) | (1 << (precision - 1));
+        (index, remaining.leading_zeros() as u8 + 1)
+    }
+    pub fn add<T: Hash + ?Sized>(&mut self, value: &T) {
+        let (index, rank) = Self::register_for(self.precision, hash_value(value));
+        self.registers[index] = self.registers[index].max(rank);
+    }
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), SketchError> {
+        if self.precision != other.precision {
+            return Err(SketchError::IncompatiblePrecision(
+                self.precision,
+                other.precision,
+            ));
+        }
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+        Ok(())
+    }
+    /// Estimated number of distinct values added.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let harmonic_sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-i32::from(*register)))
+            .sum();
+        let raw = alpha * m * m / harmonic_sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Linear counting is more accurate while many registers are still empty
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::*;
+
+    fn exact_quantile(sorted: &[f64], q: f64) -> f64 {
+        sorted[(q * (sorted.len() - 1) as f64).floor() as usize]
+    }
+
+    #[test]
+    fn test_quantiles_within_relative_accuracy() {
+        let mut sketch = DDSketch::default();
+        let values: Vec<f64> = (1..=10_000).map(|v| v as f64 * 0.37).collect();
+        for value in &values {
+            sketch.add(*value);
+        }
+        assert_eq!(sketch.count(), 10_000);
+        for q in [0.0, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99, 1.0] {
+            let expected = exact_quantile(&values, q);
+            let estimate = sketch.quantile(q).unwrap();
+            assert!(
+                relative_eq!(estimate, expected, max_relative = DEFAULT_RELATIVE_ACCURACY),
+                "q{q} estimate {estimate} not within accuracy of {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_negative_and_zero_values() {
+        let mut sketch = DDSketch::default();
+        for value in [-100.0, -10.0, 0.0, 10.0, 100.0] {
+            sketch.add(value);
+        }
+        assert_eq!(sketch.quantile(0.0), Some(-100.0));
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_eq!(sketch.quantile(1.0), Some(100.0));
+        assert!(relative_eq!(
+            sketch.quantile(0.25).unwrap(),
+            -10.0,
+            max_relative = DEFAULT_RELATIVE_ACCURACY
+        ));
+        assert_eq!(DDSketch::default().quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = DDSketch::default();
+        let mut right = DDSketch::default();
+        let mut combined = DDSketch::default();
+        for value in 1..=500 {
+            left.add(value as f64);
+            combined.add(value as f64);
+        }
+        for value in 501..=1000 {
+            right.add(value as f64);
+            combined.add(value as f64);
+        }
+        left.merge(&right).unwrap();
+        assert_eq!(left, combined);
+        let mismatched = DDSketch::new(0.05);
+        assert_eq!(
+            left.merge(&mismatched),
+            Err(SketchError::IncompatibleAccuracy(0.01, 0.05))
+        );
+    }
+
+    #[test]
+    fn test_collapse_keeps_high_quantiles() {
+        let mut sketch = DDSketch::default().with_max_bins(64);
+        for value in 1..=100_000 {
+            sketch.add(value as f64);
+        }
+        assert_eq!(sketch.count(), 100_000);
+        assert!(sketch.positive.len() <= 64);
+        assert!(relative_eq!(
+            sketch.quantile(0.99).unwrap(),
+            99_000.0,
+            max_relative = DEFAULT_RELATIVE_ACCURACY
+        ));
+    }
+
+    #[test]
+    fn test_count_min_never_undercounts() {
+        let mut sketch = CountMinSketch::new(64, 4);
+        for key in 0..1_000u32 {
+            sketch.add(&key, u64::from(key % 7));
+        }
+        sketch.add("hot", 5_000);
+        for key in 0..1_000u32 {
+            assert!(sketch.estimate(&key) >= u64::from(key % 7));
+        }
+        let hot = sketch.estimate("hot");
+        // 3000 other observations spread over 64 columns
+        assert!((5_000..5_000 + 3_000 / 16).contains(&hot));
+    }
+
+    #[test]
+    fn test_count_min_merge() {
+        let mut left = CountMinSketch::default();
+        let mut right = CountMinSketch::default();
+        left.add("a", 3);
+        right.add("a", 4);
+        right.add("b", 1);
+        left.merge(&right).unwrap();
+        assert_eq!(left.estimate("a"), 7);
+        assert_eq!(left.estimate("b"), 1);
+        assert_eq!(
+            left.merge(&CountMinSketch::new(16, 2)),
+            Err(SketchError::IncompatibleDimensions((2048, 5), (16, 2)))
+        );
+        left.clear();
+        assert_eq!(left.estimate("a"), 0);
+    }
+
+    #[test]
+    fn test_hyper_log_log_estimate() {
+        for cardinality in [0u64, 10, 1_000, 100_000] {
+            let mut sketch = HyperLogLog::default();
+            for value in 0..cardinality {
+                // Duplicates must not be counted twice
+                sketch.add(&value);
+                sketch.add(&value);
+            }
+            let estimate = sketch.estimate();
+            let tolerance = (cardinality as f64 * 0.05).max(1.0);
+            assert!(
+                (estimate - cardinality as f64).abs() <= tolerance,
+                "estimate {estimate} too far from {cardinality}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hyper_log_log_merge() {
+        let mut left = HyperLogLog::default();
+        let mut right = HyperLogLog::default();
+        let mut combined = HyperLogLog::default();
+        for value in 0..5_000 {
+            left.add(&value);
+            combined.add(&value);
+        }
+        for value in 2_500..7_500 {
+            right.add(&value);
+            combined.add(&value);
+        }
+        left.merge(&right).unwrap();
+        assert_eq!(left, combined);
+        assert_eq!(
+            left.merge(&HyperLogLog::new(8)),
+            Err(SketchError::IncompatiblePrecision(12, 8))
+        );
+    }
+}
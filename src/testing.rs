@@ -0,0 +1,410 @@
+use std::{
+    io,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixDatagram, path::PathBuf};
+
+use crate::{
+    client::DogstatsdError,
+    protocol::{parse_packet, Message, ParseError, ParsedMetric},
+    sink::{Sink, SinkResult},
+    transport::Endpoint,
+    EmissionMode, GnortClient, MetricsRegistry, RegistryConfig,
+};
+
+// How often the mock agent's receiving thread checks whether it should stop
+const MOCK_AGENT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Value of a single emission captured by [RecordingSink].
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricValue {
+    Count(i64),
+    Gauge(f64),
+    Timing(i64),
+    Set(String),
+    Distribution(Vec<f64>),
+    /// `(value, count)` bins of a pre-aggregated distribution
+    WeightedDistribution(Vec<(f64, u64)>),
+}
+
+/// A single emission captured by [RecordingSink]. Tags are sorted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricPoint {
+    pub name: String,
+    pub value: MetricValue,
+    pub tags: Vec<String>,
+}
+
+impl MetricPoint {
+    fn matches(&self, name: &str, tags: &[&str]) -> bool {
+        let mut tags = tags.to_vec();
+        tags.sort_unstable();
+        self.name == name && self.tags.iter().map(String::as_str).eq(tags)
+    }
+}
+
+/// In-memory [Sink] that records every emission so tests can make assertions on them
+/// instead of running a Datadog agent.
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    points: Mutex<Vec<MetricPoint>>,
+}
+
+/// A [MetricsRegistry] emitting into a fresh [RecordingSink] without a background emitter,
+/// call [flush](MetricsRegistry::flush) before asserting on the sink.
+pub fn recording_registry() -> (MetricsRegistry, Arc<RecordingSink>) {
+    let sink = Arc::new(RecordingSink::default());
+    let registry = MetricsRegistry::new(
+        RegistryConfig::default()
+            .with_sink(sink.clone())
+            .with_emission(EmissionMode::Manual),
+    );
+    (registry, sink)
+}
+
+impl RecordingSink {
+    fn lock(&self) -> MutexGuard<'_, Vec<MetricPoint>> {
+        self.points.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn record(&self, name: &str, value: MetricValue, tags: &[&str]) -> SinkResult {
+        let mut tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        tags.sort_unstable();
+        self.lock().push(MetricPoint {
+            name: name.to_string(),
+            value,
+            tags,
+        });
+        Ok(())
+    }
+    /// Every point emitted so far, in emission order.
+    pub fn snapshot(&self) -> Vec<MetricPoint> {
+        self.lock().clone()
+    }
+    /// Returns every point emitted so far and clears the sink.
+    pub fn take(&self) -> Vec<MetricPoint> {
+        std::mem::take(&mut *self.lock())
+    }
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+    /// Points emitted for `name` with exactly `tags`, in emission order.
+    pub fn points(&self, name: &str, tags: &[&str]) -> Vec<MetricValue> {
+        self.lock()
+            .iter()
+            .filter(|point| point.matches(name, tags))
+            .map(|point| point.value.clone())
+            .collect()
+    }
+    /// Sum of every count emitted for `name` with exactly `tags`, `None` if there were none.
+    pub fn count_total(&self, name: &str, tags: &[&str]) -> Option<i64> {
+        self.points(name, tags)
+            .into_iter()
+            .filter_map(|value| match value {
+                MetricValue::Count(count) => Some(count),
+                _ => None,
+            })
+            .reduce(|total, count| total + count)
+    }
+    /// Most recent gauge emitted for `name` with exactly `tags`.
+    pub fn last_gauge(&self, name: &str, tags: &[&str]) -> Option<f64> {
+        self.points(name, tags)
+            .into_iter()
+            .rev()
+            .find_map(|value| match value {
+                MetricValue::Gauge(gauge) => Some(gauge),
+                _ => None,
+            })
+    }
+    /// Panics unless the counts emitted for `name` with exactly `tags` add up to `expected`.
+    /// Counts are summed across flushes, so it doesn't matter how many windows they were split over.
+    #[track_caller]
+    pub fn assert_count(&self, name: &str, tags: &[&str], expected: i64) {
+        match self.count_total(name, tags) {
+            Some(total) => assert_eq!(total, expected, "count {name} {tags:?}"),
+            None => panic!(
+                "no count {name} {tags:?} was emitted, emitted points were: {:#?}",
+                self.snapshot()
+            ),
+        }
+    }
+    /// Panics unless the most recent gauge emitted for `name` with exactly `tags` is `expected`.
+    #[track_caller]
+    pub fn assert_gauge(&self, name: &str, tags: &[&str], expected: f64) {
+        match self.last_gauge(name, tags) {
+            Some(value) => assert_eq!(value, expected, "gauge {name} {tags:?}"),
+            None => panic!(
+                "no gauge {name} {tags:?} was emitted, emitted points were: {:#?}",
+                self.snapshot()
+            ),
+        }
+    }
+    /// Panics if anything was emitted for `name` with exactly `tags`.
+    #[track_caller]
+    pub fn assert_not_emitted(&self, name: &str, tags: &[&str]) {
+        let points = self.points(name, tags);
+        assert!(points.is_empty(), "{name} {tags:?} was emitted: {points:?}");
+    }
+}
+
+impl Sink for RecordingSink {
+    fn count(&self, name: &str, value: i64, tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::Count(value), tags)
+    }
+    fn gauge(&self, name: &str, value: f64, tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::Gauge(value), tags)
+    }
+    fn timing(&self, name: &str, milliseconds: i64, tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::Timing(milliseconds), tags)
+    }
+    fn set(&self, name: &str, value: &str, tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::Set(value.to_string()), tags)
+    }
+    fn distribution(&self, name: &str, values: &[f64], tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::Distribution(values.to_vec()), tags)
+    }
+    fn weighted_distribution(&self, name: &str, bins: &[(f64, u64)], tags: &[&str]) -> SinkResult {
+        self.record(name, MetricValue::WeightedDistribution(bins.to_vec()), tags)
+    }
+}
+
+enum AgentSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    UnixDatagram(UnixDatagram),
+}
+
+impl AgentSocket {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AgentSocket::Udp(socket) => socket.recv(buf),
+            #[cfg(unix)]
+            AgentSocket::UnixDatagram(socket) => socket.recv(buf),
+        }
+    }
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            AgentSocket::Udp(socket) => socket.set_read_timeout(Some(timeout)),
+            #[cfg(unix)]
+            AgentSocket::UnixDatagram(socket) => socket.set_read_timeout(Some(timeout)),
+        }
+    }
+}
+
+/// Stand-in for the Datadog agent that binds a local socket and keeps every datagram it
+/// receives, so tests can assert on exactly what [GnortClient] put on the wire.
+/// Stops receiving when dropped.
+pub struct MockAgent {
+    endpoint: Endpoint,
+    packets: Arc<Mutex<Vec<String>>>,
+    stop: Arc<AtomicBool>,
+    receiver: Option<JoinHandle<()>>,
+    #[cfg(unix)]
+    socket_path: Option<PathBuf>,
+}
+
+impl MockAgent {
+    /// Listens on an ephemeral UDP port on localhost.
+    pub fn bind_udp() -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let endpoint = Endpoint::Udp(socket.local_addr()?.to_string());
+        Self::start(AgentSocket::Udp(socket), endpoint)
+    }
+    /// Listens on a Unix datagram socket in the temp directory, removed when the agent is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix_datagram() -> io::Result<Self> {
+        static NEXT_SOCKET: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "gnort-mock-agent-{}-{}.socket",
+            std::process::id(),
+            NEXT_SOCKET.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        let mut agent = Self::start(
+            AgentSocket::UnixDatagram(socket),
+            Endpoint::UnixDatagram(path.clone()),
+        )?;
+        agent.socket_path = Some(path);
+        Ok(agent)
+    }
+    fn start(socket: AgentSocket, endpoint: Endpoint) -> io::Result<Self> {
+        socket.set_read_timeout(MOCK_AGENT_POLL_INTERVAL)?;
+        let packets = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let receiver = {
+            let (packets, stop) = (packets.clone(), stop.clone());
+            std::thread::Builder::new()
+                .name("gnort-mock-agent".to_string())
+                .spawn(move || {
+                    let mut buf = vec![0; 65_536];
+                    while !stop.load(Ordering::Relaxed) {
+                        // Timeouts just mean nothing was sent, check whether to stop and try again
+                        if let Ok(len) = socket.recv(&mut buf) {
+                            let packet = String::from_utf8_lossy(&buf[..len]).into_owned();
+                            packets
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .push(packet);
+                        }
+                    }
+                })?
+        };
+        Ok(Self {
+            endpoint,
+            packets,
+            stop,
+            receiver: Some(receiver),
+            #[cfg(unix)]
+            socket_path: None,
+        })
+    }
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+    /// A client without a namespace or extra default tags sending to this agent.
+    pub fn client(&self) -> Result<GnortClient, DogstatsdError> {
+        GnortClient::with_endpoint(&self.endpoint, None, [] as [&str; 0])
+    }
+    fn lock(&self) -> MutexGuard<'_, Vec<String>> {
+        self.packets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Every datagram received so far, in order.
+    pub fn packets(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+    /// Waits up to `timeout` for at least `count` datagrams to arrive and returns all of them.
+    pub fn wait_for_packets(&self, count: usize, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let packets = self.packets();
+            if packets.len() >= count || Instant::now() >= deadline {
+                return packets;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    /// Every line received so far, decoded.
+    pub fn messages(&self) -> Result<Vec<Message>, ParseError> {
+        let mut messages = Vec::new();
+        for packet in self.packets() {
+            messages.extend(parse_packet(&packet)?);
+        }
+        Ok(messages)
+    }
+    /// Every metric line received so far, decoded.
+    pub fn metrics(&self) -> Result<Vec<ParsedMetric>, ParseError> {
+        Ok(self
+            .messages()?
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::Metric(metric) => Some(metric),
+                _ => None,
+            })
+            .collect())
+    }
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+}
+
+impl Drop for MockAgent {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{instrument::GaugeAggregation, protocol::MetricKind, EmissionMode, Metric};
+
+    #[test]
+    fn test_recording_sink() {
+        let (registry, sink) = recording_registry();
+        let count = registry
+            .register_count(Metric::from("gnort.test.testing.count").with_tags(["tag:y", "tag:x"]))
+            .unwrap();
+        let gauge = registry
+            .register_gauge_with_aggregation("gnort.test.testing.gauge", GaugeAggregation::Max)
+            .unwrap();
+        count.fetch_add(2);
+        gauge.record(4.0);
+        registry.flush();
+        count.increment();
+        gauge.record(1.0);
+        registry.flush();
+        // Tag order doesn't matter, counts add up across flushes
+        sink.assert_count("gnort.test.testing.count", &["tag:x", "tag:y"], 3);
+        sink.assert_gauge("gnort.test.testing.gauge", &[], 1.0);
+        sink.assert_not_emitted("gnort.test.testing.count", &["tag:x"]);
+        let snapshot = sink.take();
+        assert!(snapshot.contains(&MetricPoint {
+            name: "gnort.test.testing.gauge".to_string(),
+            value: MetricValue::Gauge(4.0),
+            tags: vec![],
+        }));
+        assert!(sink.snapshot().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "no count gnort.test.testing.missing")]
+    fn test_assert_count_missing() {
+        let (_registry, sink) = recording_registry();
+        sink.assert_count("gnort.test.testing.missing", &[], 1);
+    }
+
+    fn assert_registry_reaches_agent(agent: MockAgent) {
+        let registry = MetricsRegistry::new(
+            RegistryConfig::default()
+                .with_client(agent.client().unwrap())
+                .with_emission(EmissionMode::Manual),
+        );
+        let count = registry
+            .register_count(Metric::from("gnort.test.agent.count").with_tags(["tag:x"]))
+            .unwrap();
+        count.fetch_add(5);
+        registry.flush();
+        let packets = agent.wait_for_packets(1, Duration::from_secs(5));
+        // The count and the registry's own timing gauge share a batched datagram
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].starts_with("gnort.test.agent.count:5|c|#tag:x"));
+        let metrics = agent.metrics().unwrap();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].kind, MetricKind::Count);
+        assert_eq!(metrics[0].float_values(), Some(vec![5.0]));
+    }
+
+    #[test]
+    fn test_mock_agent_udp() {
+        assert_registry_reaches_agent(MockAgent::bind_udp().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mock_agent_unix_datagram() {
+        let agent = MockAgent::bind_unix_datagram().unwrap();
+        let client = agent.client().unwrap();
+        client.event("deployed", "v2", ["team:metrics"]).unwrap();
+        agent.wait_for_packets(1, Duration::from_secs(5));
+        match agent.messages().unwrap().as_slice() {
+            [Message::Event(event)] => assert_eq!(event.title, "deployed"),
+            other => panic!("expected an event, was: {other:?}"),
+        }
+        agent.clear();
+        assert_registry_reaches_agent(agent);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{env, fmt, io, net::UdpSocket, str::FromStr};
+#[cfg(unix)]
+use std::{
+    io::Write,
+    os::unix::net::{UnixDatagram, UnixStream},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::client::{DOGSTATSD_URL_ENV, STATSD_HOST_ENV, STATSD_PORT_ENV};
+
+const DEFAULT_ORIGIN: &str = "0.0.0.0:0";
+// Port 8125(UDP) is for metrics,
+// port 8126(TCP) is for Datadog APM (tracing)
+
+// Stays under a 1500 byte Ethernet MTU after IP and UDP headers
+const DEFAULT_UDP_MAX_PACKET_SIZE: usize = 1432;
+// The agent's default dogstatsd_buffer_size
+#[cfg(unix)]
+const DEFAULT_UDS_MAX_PACKET_SIZE: usize = 8192;
+
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_PORT: &str = "8125";
+
+/// Where [GnortClient](crate::GnortClient) sends its datagrams.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `host:port` of the agent, `udp://host:port`
+    Udp(String),
+    /// Path of the agent's datagram socket, `unix:///var/run/datadog/dsd.socket`
+    /// or `unixgram:///var/run/datadog/dsd.socket`
+    #[cfg(unix)]
+    UnixDatagram(PathBuf),
+    /// Path of the agent's stream socket, `unixstream:///var/run/datadog/dsd.socket`.
+    /// Every payload is prefixed with its length as a little-endian u32.
+    #[cfg(unix)]
+    UnixStream(PathBuf),
+}
+
+impl Endpoint {
+    /// Parses `DD_DOGSTATSD_URL` when it's set, otherwise UDP to `STATSD_HOST`:`STATSD_PORT`
+    /// defaulting to `0.0.0.0:8125`.
+    pub fn from_env() -> io::Result<Self> {
+        if let Ok(url) = env::var(DOGSTATSD_URL_ENV) {
+            return url.parse();
+        }
+        let statsd_host = env::var(STATSD_HOST_ENV).unwrap_or(DEFAULT_HOST.to_string());
+        let statsd_port = env::var(STATSD_PORT_ENV).unwrap_or(DEFAULT_PORT.to_string());
+        Ok(Endpoint::Udp(format!("{}:{}", statsd_host, statsd_port)))
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = io::Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid DogStatsD URL {url:?}, {reason}"),
+            )
+        };
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return Err(invalid("expected scheme://address"));
+        };
+        if rest.is_empty() {
+            return Err(invalid("address is empty"));
+        }
+        match scheme {
+            "udp" => Ok(Endpoint::Udp(rest.to_string())),
+            #[cfg(unix)]
+            "unix" | "unixgram" => Ok(Endpoint::UnixDatagram(PathBuf::from(rest))),
+            #[cfg(unix)]
+            "unixstream" => Ok(Endpoint::UnixStream(PathBuf::from(rest))),
+            _ => Err(invalid("unsupported scheme")),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Udp(address) => write!(f, "udp://{address}"),
+            #[cfg(unix)]
+            Endpoint::UnixDatagram(path) => write!(f, "unix://{}", path.display()),
+            #[cfg(unix)]
+            Endpoint::UnixStream(path) => write!(f, "unixstream://{}", path.display()),
+        }
+    }
+}
+
+/// Socket a [GnortClient](crate::GnortClient) writes to, every [Transport::send] is one datagram.
+/// Usually connected from an [Endpoint], Unix sockets can also be converted from existing ones
+/// such as one half of a `pair()`.
+#[derive(Debug)]
+pub enum Transport {
+    Udp {
+        socket: UdpSocket,
+        target: String,
+    },
+    /// Sockets converted from a connected [UnixDatagram] have no `target`
+    #[cfg(unix)]
+    UnixDatagram {
+        socket: UnixDatagram,
+        target: Option<PathBuf>,
+    },
+    /// Writes are serialized so length-prefixed payloads don't interleave
+    #[cfg(unix)]
+    UnixStream(Mutex<UnixStream>),
+}
+
+impl Transport {
+    pub fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Udp(target) => Ok(Transport::Udp {
+                socket: UdpSocket::bind(DEFAULT_ORIGIN)?,
+                target: target.clone(),
+            }),
+            #[cfg(unix)]
+            Endpoint::UnixDatagram(path) => Ok(Transport::UnixDatagram {
+                socket: UnixDatagram::unbound()?,
+                target: Some(path.clone()),
+            }),
+            #[cfg(unix)]
+            Endpoint::UnixStream(path) => Ok(UnixStream::connect(path)?.into()),
+        }
+    }
+    /// How large batched packets should get, 1432 bytes for UDP and 8192 bytes for Unix domain sockets.
+    pub fn default_max_packet_size(&self) -> usize {
+        match self {
+            Transport::Udp { .. } => DEFAULT_UDP_MAX_PACKET_SIZE,
+            #[cfg(unix)]
+            Transport::UnixDatagram { .. } | Transport::UnixStream(_) => {
+                DEFAULT_UDS_MAX_PACKET_SIZE
+            }
+        }
+    }
+    pub fn send(&self, payload: &[u8]) -> io::Result<()> {
+        match self {
+            // Resolved on every send so DNS changes are picked up
+            Transport::Udp { socket, target } => socket.send_to(payload, target.as_str()).map(drop),
+            #[cfg(unix)]
+            // Addressed on every send so an agent that isn't listening yet doesn't fail the client
+            Transport::UnixDatagram {
+                socket,
+                target: Some(target),
+            } => socket.send_to(payload, target).map(drop),
+            #[cfg(unix)]
+            Transport::UnixDatagram {
+                socket,
+                target: None,
+            } => socket.send(payload).map(drop),
+            #[cfg(unix)]
+            Transport::UnixStream(stream) => {
+                let length = u32::try_from(payload.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "payload is too large")
+                })?;
+                let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+                stream.write_all(&length.to_le_bytes())?;
+                stream.write_all(payload)
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixDatagram> for Transport {
+    fn from(socket: UnixDatagram) -> Self {
+        Transport::UnixDatagram {
+            socket,
+            target: None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Transport {
+    fn from(stream: UnixStream) -> Self {
+        Transport::UnixStream(Mutex::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            "udp://localhost:8125".parse::<Endpoint>().unwrap(),
+            Endpoint::Udp("localhost:8125".to_string())
+        );
+        assert_eq!(
+            "unix:///var/run/datadog/dsd.socket"
+                .parse::<Endpoint>()
+                .unwrap(),
+            Endpoint::UnixDatagram(PathBuf::from("/var/run/datadog/dsd.socket"))
+        );
+        let stream = "unixstream:///var/run/datadog/dsd.socket";
+        assert_eq!(stream.parse::<Endpoint>().unwrap().to_string(), stream);
+        for invalid in ["localhost:8125", "tcp://localhost:8125", "unix://"] {
+            let err = invalid.parse::<Endpoint>().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_unix_datagram_agent_started_later() {
+        let path =
+            std::env::temp_dir().join(format!("gnort-late-agent-{}.socket", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let transport = Transport::connect(&Endpoint::UnixDatagram(path.clone())).unwrap();
+        assert!(transport.send(b"test.count:1|c").is_err());
+        let agent = UnixDatagram::bind(&path).unwrap();
+        transport.send(b"test.count:2|c").unwrap();
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"test.count:2|c");
+        std::fs::remove_file(&path).unwrap();
+    }
+}